@@ -0,0 +1,136 @@
+use std::time::Duration;
+use atlas::Image;
+use data;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Loop,
+    /// Stops on the last frame
+    Once,
+    /// Runs forwards then backwards
+    PingPong,
+}
+
+/// A named run of frames in a sheet
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub name: String,
+    pub mode: Mode,
+    pub first: usize,
+    pub last: usize,
+}
+
+/// What a sprite reports as it animates
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SpriteEvent {
+    /// The end of the animation was reached, every cycle for looping ones
+    Finished,
+    /// A named marker on the frame just shown, like a footstep
+    Marker(String),
+}
+
+/// The frames of a GIF along with anything its sidecar file says about them
+pub struct SpriteSheet {
+    pub frames: Vec<(Image, Duration)>,
+    /// Always has at least one animation, the whole sheet looping as "default" unless the sidecar says otherwise
+    pub animations: Vec<Animation>,
+    /// Point in the frame, from its bottom left, that sits on the entity's bottom left
+    pub origin: [i32; 2],
+    /// Box the sprite can touch things with as x, y, width, height from the frame's bottom left, one per frame
+    pub hitboxes: Vec<[i32; 4]>,
+    /// Markers fired as each frame is shown
+    pub markers: Vec<(usize, String)>,
+}
+
+impl SpriteSheet {
+    /// `sidecar` is the text file next to the GIF `name`, if there is one
+    pub fn new(name: &str, frames: Vec<(Image, Duration)>, sidecar: Option<&str>) -> SpriteSheet {
+        let full = [0, 0, frames[0].0.width as i32, frames[0].0.height as i32];
+        let mut sheet = SpriteSheet {
+            animations: Vec::new(),
+            origin: [0, 0],
+            hitboxes: vec![full; frames.len()],
+            markers: Vec::new(),
+            frames: frames,
+        };
+        for line in sidecar.unwrap_or("").lines() {
+            let values = data::values(line);
+            if values[0].is_empty() {
+                continue;
+            }
+            if let Err(problem) = sheet.apply(&values) {
+                data::report(name, line, &problem);
+            }
+        }
+        if sheet.animations.is_empty() {
+            sheet.animations.push(Animation {
+                name: "default".to_string(),
+                mode: Mode::Loop,
+                first: 0,
+                last: sheet.frames.len() - 1,
+            });
+        }
+        sheet
+    }
+
+    fn apply(&mut self, values: &[&str]) -> Result<(), String> {
+        let frames = self.frames.len();
+        let number = |i: usize| -> Result<i32, String> { data::parse(data::get(values, i)) };
+        let frame = |i: usize| -> Result<usize, String> {
+            let frame = try!(data::parse(data::get(values, i)));
+            if frame < frames { Ok(frame) } else { Err(format!("no frame {}", frame)) }
+        };
+        match values[0] {
+            // animation,name,loop|once|pingpong[,first,last]
+            "animation" => {
+                let name = data::get(values, 1);
+                if name.is_empty() {
+                    return Err("missing animation name".to_string());
+                }
+                let mode = match data::get(values, 2) {
+                    "" | "loop" => Mode::Loop,
+                    "once" => Mode::Once,
+                    "pingpong" => Mode::PingPong,
+                    other => return Err(format!("unknown animation mode {}", other)),
+                };
+                let (first, last) = if values.len() > 3 { (try!(frame(3)), try!(frame(4))) } else { (0, frames - 1) };
+                if first > last {
+                    return Err("first frame is after the last".to_string());
+                }
+                self.animations.push(Animation {
+                    name: name.to_string(),
+                    mode: mode,
+                    first: first,
+                    last: last,
+                });
+            }
+            "origin" => self.origin = [try!(number(1)), try!(number(2))],
+            // hitbox,x,y,width,height[,frame] where leaving out the frame sets every frame
+            "hitbox" => {
+                let hitbox = [try!(number(1)), try!(number(2)), try!(number(3)), try!(number(4))];
+                if values.len() > 5 {
+                    self.hitboxes[try!(frame(5))] = hitbox;
+                } else {
+                    for frame in &mut self.hitboxes {
+                        *frame = hitbox;
+                    }
+                }
+            }
+            // event,frame,name
+            "event" => {
+                let frame = try!(frame(1));
+                let name = data::get(values, 2);
+                if name.is_empty() {
+                    return Err("missing event name".to_string());
+                }
+                self.markers.push((frame, name.to_string()));
+            }
+            other => return Err(format!("unknown sprite value {}", other)),
+        }
+        Ok(())
+    }
+
+    pub fn animation(&self, name: &str) -> Option<usize> {
+        self.animations.iter().position(|animation| animation.name == name)
+    }
+}
@@ -0,0 +1,114 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::rc::Rc;
+use glium;
+use glium::texture::Texture2d;
+
+/// Atlas width in pixels, wide enough for the largest image
+const WIDTH: u32 = 1024;
+
+/// Where one image sits in the atlas, the position is only known once the atlas is built
+pub struct Region {
+    position: Cell<[u32; 2]>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    pub fn position(&self) -> [u32; 2] {
+        self.position.get()
+    }
+}
+
+pub type Image = Rc<Region>;
+
+/// Collects every image while loading so they can all be drawn from one texture
+pub struct AtlasBuilder {
+    images: Vec<(Image, Vec<u8>)>,
+}
+
+impl AtlasBuilder {
+    pub fn new() -> AtlasBuilder {
+        AtlasBuilder { images: Vec::new() }
+    }
+
+    /// `data` is palette indices, top row first
+    pub fn add(&mut self, width: u32, height: u32, data: Vec<u8>) -> Image {
+        let image = Rc::new(Region {
+            position: Cell::new([0, 0]),
+            width: width,
+            height: height,
+        });
+        self.images.push((image.clone(), data));
+        image
+    }
+
+    /// Packs the images into rows, tallest first, and uploads the atlas
+    pub fn build<F>(mut self, facade: &F) -> Texture2d
+        where F: glium::backend::Facade
+    {
+        self.images.sort_by(|a, b| b.0.height.cmp(&a.0.height));
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for &(ref image, _) in &self.images {
+            if x + image.width > WIDTH {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+            image.position.set([x, y]);
+            x += image.width;
+            row_height = ::std::cmp::max(row_height, image.height);
+        }
+        let height = y + row_height;
+        // Anything not covered by an image is transparent
+        let mut pixels = vec![4; (WIDTH * height) as usize];
+        for &(ref image, ref data) in &self.images {
+            let position = image.position();
+            for row in 0..image.height {
+                let start = ((position[1] + row) * WIDTH + position[0]) as usize;
+                let source = (row * image.width) as usize;
+                pixels[start..start + image.width as usize].copy_from_slice(&data[source..source + image.width as usize]);
+            }
+        }
+        Texture2d::new(facade,
+                       glium::texture::RawImage2d {
+                           data: Cow::Owned(pixels),
+                           width: WIDTH,
+                           height: height,
+                           format: glium::texture::ClientFormat::U8,
+                       })
+            .unwrap()
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct SpriteVertex {
+    /// Screen pixels from the bottom left
+    position: [f32; 2],
+    /// Atlas pixels from the top left
+    tex_coord: [f32; 2],
+}
+
+implement_vertex!(SpriteVertex, position, tex_coord);
+
+/// Two triangles per sprite in draw order so the whole screen is one draw call, skipping anything off screen
+pub fn vertices(sprites: &[(Image, [i32; 2], bool)]) -> Vec<SpriteVertex> {
+    let mut vertices = Vec::new();
+    for &(ref image, offset, flip) in sprites {
+        let (width, height) = (image.width as i32, image.height as i32);
+        if offset[0] + width <= 0 || offset[0] >= 160 || offset[1] + height <= 0 || offset[1] >= 144 {
+            continue;
+        }
+        let position = image.position();
+        let (left, right) = if flip { (position[0] + image.width, position[0]) } else { (position[0], position[0] + image.width) };
+        let (top, bottom) = (position[1], position[1] + image.height);
+        let corner = |dx: i32, dy: i32| {
+            SpriteVertex {
+                position: [(offset[0] + dx * width) as f32, (offset[1] + dy * height) as f32],
+                tex_coord: [(if dx == 0 { left } else { right }) as f32, (if dy == 0 { bottom } else { top }) as f32],
+            }
+        };
+        vertices.extend_from_slice(&[corner(0, 0), corner(1, 0), corner(0, 1), corner(0, 1), corner(1, 0), corner(1, 1)]);
+    }
+    vertices
+}
@@ -0,0 +1,168 @@
+use data;
+
+/// How the camera follows the player, loaded from `camera` and adjustable per level
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSettings {
+    /// Distance the player can move from the centre of the screen before the camera follows
+    pub deadzone_x: i32,
+    pub deadzone_y: i32,
+    /// How far ahead of the player to look in the direction it's facing
+    pub lookahead: i32,
+    /// Fraction of the remaining distance covered each frame, 1.0 snaps straight there
+    pub smoothing: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            deadzone_x: 8,
+            deadzone_y: 16,
+            lookahead: 24,
+            smoothing: 0.2,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn parse(string: &str) -> CameraSettings {
+        let mut settings = CameraSettings::default();
+        data::key_values("camera", string, |name, value| settings.set(name, value));
+        settings
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "deadzone_x" => self.deadzone_x = try!(data::parse(value)),
+            "deadzone_y" => self.deadzone_y = try!(data::parse(value)),
+            "lookahead" => self.lookahead = try!(data::parse(value)),
+            "smoothing" => self.smoothing = try!(data::parse(value)),
+            _ => return Err(format!("unknown camera value {}", name)),
+        }
+        Ok(())
+    }
+}
+
+pub struct Camera {
+    pub settings: CameraSettings,
+    /// Regions as x, y, width, height in level pixels that keep the view inside them while the player is
+    pub locks: Vec<[i32; 4]>,
+    x: f32,
+    y: f32,
+    look: f32,
+    last_y: i32,
+    snap: bool,
+    shake: u8,
+    shake_magnitude: i32,
+    seed: u32,
+}
+
+impl Camera {
+    pub fn new(settings: CameraSettings) -> Camera {
+        Camera {
+            settings: settings,
+            locks: Vec::new(),
+            x: 0.0,
+            y: 0.0,
+            look: 0.0,
+            last_y: 0,
+            snap: true,
+            shake: 0,
+            shake_magnitude: 0,
+            seed: 0x2545_f491,
+        }
+    }
+
+    pub fn shake(&mut self, frames: u8, magnitude: i32) {
+        if frames > self.shake {
+            self.shake = frames;
+        }
+        if magnitude > self.shake_magnitude || self.shake == 0 {
+            self.shake_magnitude = magnitude;
+        }
+    }
+
+    /// Moves towards the player at `target`, `size` is the level size in pixels
+    pub fn update(&mut self, target: [i32; 2], facing: bool, size: [i32; 2], wraparound: bool) {
+        let target_x = target[0] as f32 + 8.0;
+        let target_y = target[1] as f32 + 8.0;
+        if self.snap {
+            self.x = target_x;
+            self.y = target_y;
+            self.last_y = target[1];
+            self.snap = false;
+        }
+        // Follow the player across the seam instead of panning back through the whole level
+        if wraparound {
+            let jump = target[1] - self.last_y;
+            if jump > size[1] / 2 {
+                self.y += size[1] as f32;
+            } else if jump < -size[1] / 2 {
+                self.y -= size[1] as f32;
+            }
+        }
+        self.last_y = target[1];
+
+        let settings = self.settings;
+        let look_target = (if facing { settings.lookahead } else { -settings.lookahead }) as f32;
+        self.look += (look_target - self.look) * settings.smoothing;
+        let mut goal_x = follow(self.x, target_x + self.look, settings.deadzone_x as f32);
+        let mut goal_y = follow(self.y, target_y, settings.deadzone_y as f32);
+        for lock in &self.locks {
+            if target[0] + 8 >= lock[0] && target[0] + 8 < lock[0] + lock[2] && target[1] + 8 >= lock[1] && target[1] + 8 < lock[1] + lock[3] {
+                goal_x = fit(goal_x, lock[0], lock[2], 160);
+                goal_y = fit(goal_y, lock[1], lock[3], 144);
+                break;
+            }
+        }
+        self.x += (goal_x - self.x) * settings.smoothing;
+        self.y += (goal_y - self.y) * settings.smoothing;
+        self.x = fit(self.x, 0, size[0], 160);
+        if !wraparound {
+            self.y = fit(self.y, 0, size[1], 144);
+        }
+    }
+
+    /// Bottom left of the view without any shake, for working out what's on screen
+    pub fn position(&self) -> [i32; 2] {
+        [self.x.round() as i32 - 80, self.y.round() as i32 - 72]
+    }
+
+    /// Bottom left corner of the screen in level pixels, shaken if a shake is running
+    pub fn view(&mut self) -> [i32; 2] {
+        let mut view = [self.x.round() as i32 - 80, self.y.round() as i32 - 72];
+        if self.shake > 0 {
+            self.shake -= 1;
+            let range = self.shake_magnitude * 2 + 1;
+            view[0] += (self.random() % range as u32) as i32 - self.shake_magnitude;
+            view[1] += (self.random() % range as u32) as i32 - self.shake_magnitude;
+        }
+        view
+    }
+
+    fn random(&mut self) -> u32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed
+    }
+}
+
+/// Moves `centre` just far enough that `focus` is within `deadzone` of it
+fn follow(centre: f32, focus: f32, deadzone: f32) -> f32 {
+    if focus > centre + deadzone {
+        focus - deadzone
+    } else if focus < centre - deadzone {
+        focus + deadzone
+    } else {
+        centre
+    }
+}
+
+/// Keeps a view of `view` pixels inside the span starting at `start`, centring it if the span is smaller
+fn fit(centre: f32, start: i32, length: i32, view: i32) -> f32 {
+    if length <= view {
+        start as f32 + length as f32 / 2.0
+    } else {
+        centre.max((start + view / 2) as f32).min((start + length - view / 2) as f32)
+    }
+}
@@ -0,0 +1,105 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use gif;
+use gif::SetParameter;
+
+/// Screenshots and recordings of the screen as GIFs indexed with the colours on screen
+pub struct Capture {
+    /// The running recording and the scale it was started at, which every frame has to keep
+    recording: Option<(gif::Encoder<File>, usize)>,
+}
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture { recording: None }
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Window pixels per screen pixel in the running recording
+    pub fn recording_scale(&self) -> Option<usize> {
+        self.recording.as_ref().map(|recording| recording.1)
+    }
+
+    /// Starts a new recording at `scale`, or finishes the current one
+    pub fn toggle_recording(&mut self, scale: usize) -> io::Result<()> {
+        if self.recording.take().is_none() {
+            let mut encoder = try!(start("recording", 160 * scale, 144 * scale));
+            try!(encoder.set(gif::Repeat::Infinite));
+            self.recording = Some((encoder, scale));
+        }
+        Ok(())
+    }
+
+    /// `pixels` is the screen as read back from the GPU, bottom row first
+    pub fn screenshot(&self, pixels: &[Vec<(u8, u8, u8, u8)>], palette: &[[u8; 3]]) -> io::Result<()> {
+        write_frame(&mut try!(start("screenshot", pixels[0].len(), pixels.len())), pixels, palette)
+    }
+
+    /// Adds a frame to the recording if one is running, stopping it if the frame can't be written
+    pub fn frame(&mut self, pixels: &[Vec<(u8, u8, u8, u8)>], palette: &[[u8; 3]]) -> io::Result<()> {
+        let result = match self.recording {
+            Some((ref mut encoder, _)) => write_frame(encoder, pixels, palette),
+            None => return Ok(()),
+        };
+        if result.is_err() {
+            self.recording = None;
+        }
+        result
+    }
+}
+
+fn start(name: &str, width: usize, height: usize) -> io::Result<gif::Encoder<File>> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let file = try!(File::create(format!("{}-{}{:03}.gif", name, time.as_secs(), time.subsec_nanos() / 1_000_000)));
+    gif::Encoder::new(file, width as u16, height as u16, &[])
+}
+
+/// Writes one 20ms frame with its own palette, since the palette changes between life and death. Colours that aren't
+/// in `palette`, like those from ghosting or the grid, are added to it while there's room
+fn write_frame(encoder: &mut gif::Encoder<File>, pixels: &[Vec<(u8, u8, u8, u8)>], palette: &[[u8; 3]]) -> io::Result<()> {
+    let mut palette = palette.to_vec();
+    let mut lookup: HashMap<[u8; 3], u8> = palette.iter().enumerate().map(|(i, colour)| (*colour, i as u8)).collect();
+    let mut indices = Vec::new();
+    for row in pixels.iter().rev() {
+        for pixel in row {
+            let colour = [pixel.0, pixel.1, pixel.2];
+            let index = match lookup.get(&colour) {
+                Some(&index) => index,
+                None => {
+                    if palette.len() < 256 {
+                        palette.push(colour);
+                        (palette.len() - 1) as u8
+                    } else {
+                        nearest(&palette, colour)
+                    }
+                }
+            };
+            lookup.insert(colour, index);
+            indices.push(index);
+        }
+    }
+    let mut frame = gif::Frame::default();
+    frame.width = pixels[0].len() as u16;
+    frame.height = pixels.len() as u16;
+    frame.delay = 2;
+    frame.palette = Some(palette.iter().flat_map(|colour| colour.iter().cloned()).collect());
+    frame.buffer = Cow::Owned(indices);
+    encoder.write_frame(&frame)
+}
+
+fn nearest(palette: &[[u8; 3]], colour: [u8; 3]) -> u8 {
+    let distance = |other: &[u8; 3]| -> i32 { (0..3).map(|i| (other[i] as i32 - colour[i] as i32).pow(2)).sum() };
+    let mut best = 0;
+    for i in 1..palette.len() {
+        if distance(&palette[i]) < distance(&palette[best]) {
+            best = i;
+        }
+    }
+    best as u8
+}
@@ -0,0 +1,40 @@
+//! Reading the comma separated text files under `assets` and the settings file, where a bad line is reported and
+//! skipped instead of stopping the game
+use std::str::FromStr;
+
+/// Says why a line of `file` is being skipped
+pub fn report(file: &str, line: &str, problem: &str) {
+    println!("{}: {} in \"{}\"", file, problem, line.trim());
+}
+
+/// The trimmed values of a line
+pub fn values(line: &str) -> Vec<&str> {
+    line.split(',').map(|value| value.trim()).collect()
+}
+
+/// `values[i]`, or nothing if the line is too short
+pub fn get<'a>(values: &[&'a str], i: usize) -> &'a str {
+    values.get(i).cloned().unwrap_or("")
+}
+
+pub fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    if value.is_empty() {
+        return Err("missing value".to_string());
+    }
+    value.parse().map_err(|_| format!("bad value {}", value))
+}
+
+/// Hands each `name,value` line to `set` with the name in lowercase, reporting the lines it turns down
+pub fn key_values<F>(file: &str, string: &str, mut set: F)
+    where F: FnMut(&str, &str) -> Result<(), String>
+{
+    for line in string.lines() {
+        let values = values(line);
+        if values[0].is_empty() {
+            continue;
+        }
+        if let Err(problem) = set(&values[0].to_lowercase(), get(&values, 1)) {
+            report(file, line, &problem);
+        }
+    }
+}
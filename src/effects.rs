@@ -0,0 +1,89 @@
+//! CPU versions of what `lcd.frag` and `post.frag` do, so captures look like the screen
+
+/// How much of the previous frame is kept each frame with ghosting on
+pub const GHOSTING: f32 = 0.4;
+/// How much darker the gaps between pixels are with the LCD grid on
+pub const GRID: f32 = 0.25;
+/// How much darker every other window row is with scanlines on
+pub const SCANLINES: f32 = 0.15;
+/// Window pixels per screen pixel in scaled up captures, the smallest scale the grid shows at
+pub const CAPTURE_SCALE: usize = 3;
+
+/// Captures are only scaled up when the grid or scanlines need the room, otherwise they stay at 160x144
+pub fn capture_scale(grid: bool, scanlines: bool) -> usize {
+    if grid || scanlines { CAPTURE_SCALE } else { 1 }
+}
+
+/// 4x4 ordered dither thresholds
+const BAYER: [u8; 16] = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5];
+
+/// Rows as read back from the GPU, bottom row first
+pub type Pixels = Vec<Vec<(u8, u8, u8, u8)>>;
+
+/// Whether the pixel at `x`, `y` has switched to the new palette once `amount` of a fade is done
+pub fn dithered(x: usize, y: usize, amount: f32) -> bool {
+    (BAYER[(y & 3) * 4 + (x & 3)] as f32 + 0.5) / 16.0 < amount
+}
+
+/// Fades `pixels` into `target`, the same frame drawn with the palette being faded to
+pub fn dither(pixels: &mut Pixels, target: &Pixels, amount: f32) {
+    for (y, row) in pixels.iter_mut().enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            if dithered(x, y, amount) {
+                *pixel = target[y][x];
+            }
+        }
+    }
+}
+
+/// Blends in the previous frame like a slow LCD, `previous` should be the last frame this returned
+pub fn ghost(pixels: &mut Pixels, previous: &Pixels, amount: f32) {
+    let mix = |new: u8, old: u8| (new as f32 * (1.0 - amount) + old as f32 * amount).round() as u8;
+    for (row, old_row) in pixels.iter_mut().zip(previous.iter()) {
+        for (pixel, old) in row.iter_mut().zip(old_row.iter()) {
+            *pixel = (mix(pixel.0, old.0), mix(pixel.1, old.1), mix(pixel.2, old.2), pixel.3);
+        }
+    }
+}
+
+/// Blows each pixel up into a `factor` by `factor` square
+pub fn scale(pixels: &Pixels, factor: usize) -> Pixels {
+    let mut scaled = Vec::new();
+    for row in pixels {
+        let wide: Vec<(u8, u8, u8, u8)> = row.iter().flat_map(|pixel| (0..factor).map(move |_| *pixel)).collect();
+        for _ in 0..factor {
+            scaled.push(wide.clone());
+        }
+    }
+    scaled
+}
+
+fn darken(pixel: &mut (u8, u8, u8, u8), amount: f32) {
+    let dark = |value: u8| (value as f32 * (1.0 - amount)).round() as u8;
+    *pixel = (dark(pixel.0), dark(pixel.1), dark(pixel.2), pixel.3);
+}
+
+/// Darkens the right column and bottom row of each screen pixel in `pixels` scaled up by `factor`
+pub fn grid(pixels: &mut Pixels, factor: usize, amount: f32) {
+    if factor < 3 {
+        return;
+    }
+    for (y, row) in pixels.iter_mut().enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            if x % factor == factor - 1 || y % factor == 0 {
+                darken(pixel, amount);
+            }
+        }
+    }
+}
+
+/// Darkens every other row, counting from the bottom
+pub fn scanlines(pixels: &mut Pixels, amount: f32) {
+    for (y, row) in pixels.iter_mut().enumerate() {
+        if y % 2 == 0 {
+            for pixel in row.iter_mut() {
+                darken(pixel, amount);
+            }
+        }
+    }
+}
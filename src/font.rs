@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use atlas::{AtlasBuilder, Image};
+use data;
+use png;
+
+pub struct Glyph {
+    pub texture: Image,
+    pub width: i32,
+    /// Drawn this far above the baseline, negative for marks that hang below it
+    pub y: i32,
+}
+
+/// Glyphs sliced out of one sheet, laid out by the descriptor in `GUI/Font`
+pub struct Font {
+    glyphs: HashMap<char, Glyph>,
+    /// Drawn for characters the sheet doesn't have
+    fallback: char,
+    kerning: HashMap<(char, char), i32>,
+    /// Gap between glyphs
+    pub spacing: i32,
+    /// Width of a space
+    pub space: i32,
+    pub line_height: i32,
+}
+
+impl Font {
+    /// Reads the descriptor, `images` holds the undecoded sheets by name
+    pub fn load(atlas: &mut AtlasBuilder, descriptor: &str, images: &HashMap<String, Vec<u8>>) -> Font {
+        let mut font = Font {
+            glyphs: HashMap::new(),
+            fallback: '?',
+            kerning: HashMap::new(),
+            spacing: 1,
+            space: 3,
+            line_height: 10,
+        };
+        let mut sheet = (Vec::new(), 0);
+        let mut height = 8;
+        let mut section = "";
+        // Pixels of each glyph and accent mark as width, height, data, kept around to compose accented glyphs from
+        let mut pixels = HashMap::new();
+        let mut marks = HashMap::new();
+        for line in descriptor.lines() {
+            match line.trim() {
+                "" => continue,
+                "GLYPHS" => {
+                    section = "GLYPHS";
+                    continue;
+                }
+                "MARKS" | "COMPOSED" | "KERNING" => {
+                    section = line.trim();
+                    continue;
+                }
+                _ => (),
+            }
+            let result = match section {
+                "GLYPHS" => {
+                    // The character comes first and may itself be a comma
+                    let character = line.chars().next().unwrap();
+                    let values = data::values(&line[character.len_utf8()..]);
+                    number(&values, 1).and_then(|x| {
+                        let y = try!(number(&values, 2));
+                        let width = try!(number(&values, 3));
+                        let data = try!(slice(&sheet, x, y, width, height));
+                        font.glyphs.insert(character, glyph(atlas, width, height, 0, data.clone()));
+                        pixels.insert(character, data);
+                        Ok(())
+                    })
+                }
+                "MARKS" => {
+                    // name,x,y,width,height
+                    let values = data::values(line);
+                    number(&values, 1).and_then(|x| {
+                        let (y, width, height) = (try!(number(&values, 2)), try!(number(&values, 3)), try!(number(&values, 4)));
+                        marks.insert(values[0].to_string(), (width, height, try!(slice(&sheet, x, y, width, height))));
+                        Ok(())
+                    })
+                }
+                "COMPOSED" => {
+                    // character,base,mark[,below] puts the mark centred above or below the base glyph
+                    let values = data::values(line);
+                    let character = values[0].chars().next().unwrap();
+                    let base = data::get(&values, 1).chars().next();
+                    match (base.and_then(|base| font.glyphs.get(&base).map(|glyph| (base, glyph.width))), marks.get(data::get(&values, 2))) {
+                        (Some((base, width)), Some(&(mark_width, mark_height, ref mark))) => {
+                            let below = values.get(3) == Some(&"below");
+                            let mut data = Vec::new();
+                            if !below {
+                                data.append(&mut pad(mark, mark_width, width));
+                            }
+                            data.extend_from_slice(&pixels[&base]);
+                            if below {
+                                data.append(&mut pad(mark, mark_width, width));
+                            }
+                            font.glyphs.insert(character, glyph(atlas, width, height + mark_height, if below { -mark_height } else { 0 }, data));
+                            Ok(())
+                        }
+                        (None, _) => Err("unknown base glyph".to_string()),
+                        (_, None) => Err("unknown mark".to_string()),
+                    }
+                }
+                "KERNING" => {
+                    let mut chars = line.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(first), Some(second)) => {
+                            data::parse(line[first.len_utf8() + second.len_utf8()..].trim_left_matches(',').trim()).map(|kerning| {
+                                font.kerning.insert((first, second), kerning);
+                            })
+                        }
+                        _ => Err("kerning needs a pair of characters".to_string()),
+                    }
+                }
+                _ => {
+                    let values = data::values(line);
+                    let value = data::get(&values, 1);
+                    match values[0].to_lowercase().as_ref() {
+                        "sheet" => {
+                            match images.get(value) {
+                                Some(image) => decode(image).map(|decoded| sheet = decoded),
+                                None => Err(format!("no image {}", value)),
+                            }
+                        }
+                        "height" => data::parse(value).map(|value| height = value),
+                        "spacing" => data::parse(value).map(|value| font.spacing = value),
+                        "space" => data::parse(value).map(|value| font.space = value),
+                        "line_height" => data::parse(value).map(|value| font.line_height = value),
+                        "fallback" => data::parse(value).map(|value| font.fallback = value),
+                        name => Err(format!("unknown font value {}", name)),
+                    }
+                }
+            };
+            if let Err(problem) = result {
+                data::report("GUI/Font", line, &problem);
+            }
+        }
+        font
+    }
+
+    /// The glyph for `character`, lowercase falls back to uppercase and anything else to the fallback glyph
+    pub fn glyph(&self, character: char) -> &Glyph {
+        if let Some(glyph) = self.glyphs.get(&character) {
+            return glyph;
+        }
+        if let Some(glyph) = character.to_uppercase().next().and_then(|upper| self.glyphs.get(&upper)) {
+            return glyph;
+        }
+        &self.glyphs[&self.fallback]
+    }
+
+    pub fn kerning(&self, previous: char, character: char) -> i32 {
+        let upper = |c: char| c.to_uppercase().next().unwrap_or(c);
+        *self.kerning.get(&(upper(previous), upper(character))).unwrap_or(&0)
+    }
+
+    /// Width in pixels of a line of text with the same gaps `Game::text` leaves
+    pub fn measure(&self, text: &str) -> i32 {
+        let mut width = 0;
+        let mut previous = None;
+        for character in text.chars() {
+            width += if character == ' ' { self.space } else { self.glyph(character).width };
+            if let Some(previous) = previous {
+                width += self.spacing + self.kerning(previous, character);
+            }
+            previous = Some(character);
+        }
+        width
+    }
+
+    /// Cuts characters off the end of `text` until it fits in `width` pixels
+    pub fn truncate(&self, text: &str, width: i32) -> String {
+        let mut text = text.to_string();
+        while self.measure(&text) > width {
+            text.pop();
+        }
+        text
+    }
+}
+
+fn glyph(atlas: &mut AtlasBuilder, width: i32, height: i32, y: i32, data: Vec<u8>) -> Glyph {
+    Glyph {
+        texture: atlas.add(width as u32, height as u32, data),
+        width: width,
+        y: y,
+    }
+}
+
+fn number(values: &[&str], i: usize) -> Result<i32, String> {
+    data::parse(data::get(values, i))
+}
+
+/// Copies a rectangle out of the decoded sheet, top row first
+fn slice(sheet: &(Vec<u8>, i32), x: i32, y: i32, width: i32, height: i32) -> Result<Vec<u8>, String> {
+    if x < 0 || y < 0 || width < 0 || height < 0 || x + width > sheet.1 || (y + height) * sheet.1 > sheet.0.len() as i32 {
+        return Err("outside the sheet".to_string());
+    }
+    let mut data = Vec::new();
+    for row in y..y + height {
+        for column in x..x + width {
+            data.push(sheet.0[(row * sheet.1 + column) as usize]);
+        }
+    }
+    Ok(data)
+}
+
+/// Centres rows of `width` pixels in rows of `new_width`, filling with transparency
+fn pad(data: &[u8], width: i32, new_width: i32) -> Vec<u8> {
+    let left = (new_width - width) / 2;
+    let mut padded = Vec::new();
+    for row in data.chunks(width as usize) {
+        for x in 0..new_width {
+            padded.push(if x >= left && x < left + width { row[(x - left) as usize] } else { 4 });
+        }
+    }
+    padded
+}
+
+/// Decodes a PNG into palette indices, transparent pixels and white become index 4 and the rest are shaded by brightness
+fn decode(file: &[u8]) -> Result<(Vec<u8>, i32), String> {
+    let decoder = png::Decoder::new(file);
+    let (info, mut reader) = try!(decoder.read_info().map_err(|error| error.to_string()));
+    let mut buffer = vec![0; info.buffer_size()];
+    try!(reader.next_frame(&mut buffer).map_err(|error| error.to_string()));
+    let channels = buffer.len() / (info.width * info.height) as usize;
+    let mut pixels = Vec::new();
+    for pixel in buffer.chunks(channels) {
+        let (brightness, alpha) = match channels {
+            1 => (pixel[0] as u32, 255),
+            2 => (pixel[0] as u32, pixel[1]),
+            3 => ((pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3, 255),
+            _ => ((pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3, pixel[3]),
+        };
+        pixels.push(if alpha < 128 || brightness == 255 { 4 } else { (brightness * 4 / 256) as u8 });
+    }
+    Ok((pixels, info.width as i32))
+}
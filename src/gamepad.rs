@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use gilrs;
+
+pub enum PadEvent {
+    Connected(usize, String),
+    Disconnected(usize),
+    /// A button by name, bound in settings as `Pad` followed by the name
+    Button(usize, String, bool),
+    /// Left stick or d-pad axis, 0 for x and 1 for y, from -1 to 1 with up positive
+    Stick(usize, usize, f32),
+}
+
+/// Where gamepad events come from, so something other than real hardware can drive the game
+pub trait PadSource {
+    fn next_event(&mut self) -> Option<PadEvent>;
+}
+
+pub struct GilrsSource {
+    gilrs: gilrs::Gilrs,
+    /// Pads that were already plugged in are reported as connecting on the first poll
+    pending: Vec<PadEvent>,
+}
+
+impl GilrsSource {
+    pub fn new() -> Option<GilrsSource> {
+        match gilrs::Gilrs::new() {
+            Ok(gilrs) => {
+                let pending = gilrs.gamepads().map(|(id, gamepad)| PadEvent::Connected(id, gamepad.name().to_string())).collect();
+                Some(GilrsSource {
+                    gilrs: gilrs,
+                    pending: pending,
+                })
+            }
+            Err(error) => {
+                println!("Gamepads unavailable: {}", error);
+                None
+            }
+        }
+    }
+}
+
+impl PadSource for GilrsSource {
+    fn next_event(&mut self) -> Option<PadEvent> {
+        if let Some(event) = self.pending.pop() {
+            return Some(event);
+        }
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            match event {
+                gilrs::EventType::Connected => return Some(PadEvent::Connected(id, self.gilrs.gamepad(id).name().to_string())),
+                gilrs::EventType::Disconnected => return Some(PadEvent::Disconnected(id)),
+                gilrs::EventType::ButtonPressed(button, _) => return Some(PadEvent::Button(id, format!("{:?}", button), true)),
+                gilrs::EventType::ButtonReleased(button, _) => return Some(PadEvent::Button(id, format!("{:?}", button), false)),
+                gilrs::EventType::AxisChanged(gilrs::Axis::LeftStickX, value, _) => return Some(PadEvent::Stick(id, 0, value)),
+                gilrs::EventType::AxisChanged(gilrs::Axis::LeftStickY, value, _) => return Some(PadEvent::Stick(id, 1, value)),
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+/// Used when no gamepad backend could be started
+pub struct NoPads;
+
+impl PadSource for NoPads {
+    fn next_event(&mut self) -> Option<PadEvent> {
+        None
+    }
+}
+
+/// Turns pad events into named presses and releases for the bindings in settings
+pub struct Gamepads {
+    source: Box<PadSource>,
+    pub connected: Vec<(usize, String)>,
+    sticks: HashMap<usize, [f32; 2]>,
+    /// Buttons held on each pad, released if it gets unplugged
+    held: Vec<(usize, String)>,
+}
+
+impl Gamepads {
+    pub fn new(source: Box<PadSource>) -> Gamepads {
+        Gamepads {
+            source: source,
+            connected: Vec::new(),
+            sticks: HashMap::new(),
+            held: Vec::new(),
+        }
+    }
+
+    /// `selected` is a pad name, "any" or "off", and the stick has to pass `deadzone` to count as a direction
+    pub fn poll(&mut self, selected: &str, deadzone: f32) -> Vec<(String, bool)> {
+        let mut presses = Vec::new();
+        while let Some(event) = self.source.next_event() {
+            match event {
+                PadEvent::Connected(id, name) => {
+                    self.connected.retain(|pad| pad.0 != id);
+                    self.connected.push((id, name));
+                }
+                PadEvent::Disconnected(id) => {
+                    for &(pad, ref button) in &self.held {
+                        if pad == id {
+                            presses.push((button.clone(), false));
+                        }
+                    }
+                    self.held.retain(|held| held.0 != id);
+                    self.sticks.remove(&id);
+                    self.connected.retain(|pad| pad.0 != id);
+                }
+                PadEvent::Button(id, button, pressed) => {
+                    let accepted = self.accepts(id, selected);
+                    self.press(&mut presses, id, format!("Pad{}", button), pressed, accepted);
+                }
+                PadEvent::Stick(id, axis, value) => {
+                    let accepted = self.accepts(id, selected);
+                    let old = self.sticks.get(&id).cloned().unwrap_or([0.0, 0.0])[axis];
+                    self.sticks.entry(id).or_insert([0.0, 0.0])[axis] = value;
+                    let names = if axis == 0 { ["PadStickLeft", "PadStickRight"] } else { ["PadStickDown", "PadStickUp"] };
+                    for (i, name) in names.iter().enumerate() {
+                        let sign = if i == 0 { -1.0 } else { 1.0 };
+                        let was = old * sign > deadzone;
+                        let is = value * sign > deadzone;
+                        if was != is {
+                            self.press(&mut presses, id, name.to_string(), is, accepted);
+                        }
+                    }
+                }
+            }
+        }
+        presses
+    }
+
+    /// Only `accepted` pads can press, but anything held is always let go so it can't stay down after the selected pad changes
+    fn press(&mut self, presses: &mut Vec<(String, bool)>, id: usize, button: String, pressed: bool, accepted: bool) {
+        let held = self.held.iter().any(|held| held.0 == id && held.1 == button);
+        if pressed && accepted && !held {
+            self.held.push((id, button.clone()));
+            presses.push((button, true));
+        } else if !pressed && held {
+            self.held.retain(|held| held.0 != id || held.1 != button);
+            presses.push((button, false));
+        }
+    }
+
+    fn accepts(&self, id: usize, selected: &str) -> bool {
+        match selected {
+            "any" => true,
+            "off" => false,
+            name => self.connected.iter().any(|pad| pad.0 == id && pad.1 == name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use super::{Gamepads, PadEvent, PadSource};
+
+    /// Hands out whatever events the test queues up
+    struct FakePad {
+        events: Rc<RefCell<VecDeque<PadEvent>>>,
+    }
+
+    impl PadSource for FakePad {
+        fn next_event(&mut self) -> Option<PadEvent> {
+            self.events.borrow_mut().pop_front()
+        }
+    }
+
+    fn gamepads() -> (Gamepads, Rc<RefCell<VecDeque<PadEvent>>>) {
+        let events = Rc::new(RefCell::new(VecDeque::new()));
+        (Gamepads::new(Box::new(FakePad { events: events.clone() })), events)
+    }
+
+    fn presses(list: &[(&str, bool)]) -> Vec<(String, bool)> {
+        list.iter().map(|&(name, pressed)| (name.to_string(), pressed)).collect()
+    }
+
+    #[test]
+    fn stick_inside_deadzone() {
+        let (mut pads, events) = gamepads();
+        events.borrow_mut().push_back(PadEvent::Connected(0, "Pad".to_string()));
+        events.borrow_mut().push_back(PadEvent::Stick(0, 0, -0.3));
+        events.borrow_mut().push_back(PadEvent::Stick(0, 1, 0.3));
+        assert_eq!(pads.poll("any", 0.4), presses(&[]));
+        events.borrow_mut().push_back(PadEvent::Stick(0, 0, -0.5));
+        assert_eq!(pads.poll("any", 0.4), presses(&[("PadStickLeft", true)]));
+    }
+
+    #[test]
+    fn unplugging_releases() {
+        let (mut pads, events) = gamepads();
+        events.borrow_mut().push_back(PadEvent::Connected(0, "Pad".to_string()));
+        events.borrow_mut().push_back(PadEvent::Stick(0, 0, -1.0));
+        events.borrow_mut().push_back(PadEvent::Button(0, "South".to_string(), true));
+        assert_eq!(pads.poll("any", 0.4), presses(&[("PadStickLeft", true), ("PadSouth", true)]));
+        events.borrow_mut().push_back(PadEvent::Disconnected(0));
+        assert_eq!(pads.poll("any", 0.4), presses(&[("PadStickLeft", false), ("PadSouth", false)]));
+        assert!(pads.connected.is_empty());
+    }
+
+    #[test]
+    fn selected_pad() {
+        let (mut pads, events) = gamepads();
+        events.borrow_mut().push_back(PadEvent::Connected(0, "One".to_string()));
+        events.borrow_mut().push_back(PadEvent::Connected(1, "Two".to_string()));
+        pads.poll("any", 0.4);
+        let press = |events: &Rc<RefCell<VecDeque<PadEvent>>>, pressed: bool| {
+            events.borrow_mut().push_back(PadEvent::Button(0, "South".to_string(), pressed));
+            events.borrow_mut().push_back(PadEvent::Button(1, "East".to_string(), pressed));
+        };
+        press(&events, true);
+        assert_eq!(pads.poll("off", 0.4), presses(&[]));
+        press(&events, false);
+        pads.poll("off", 0.4);
+        press(&events, true);
+        assert_eq!(pads.poll("any", 0.4), presses(&[("PadSouth", true), ("PadEast", true)]));
+        press(&events, false);
+        pads.poll("any", 0.4);
+        press(&events, true);
+        assert_eq!(pads.poll("Two", 0.4), presses(&[("PadEast", true)]));
+    }
+
+    #[test]
+    fn release_after_changing_pad() {
+        let (mut pads, events) = gamepads();
+        events.borrow_mut().push_back(PadEvent::Connected(0, "One".to_string()));
+        events.borrow_mut().push_back(PadEvent::Connected(1, "Two".to_string()));
+        events.borrow_mut().push_back(PadEvent::Button(0, "South".to_string(), true));
+        events.borrow_mut().push_back(PadEvent::Stick(0, 1, 1.0));
+        assert_eq!(pads.poll("One", 0.4), presses(&[("PadSouth", true), ("PadStickUp", true)]));
+        events.borrow_mut().push_back(PadEvent::Button(0, "South".to_string(), false));
+        events.borrow_mut().push_back(PadEvent::Stick(0, 1, 0.0));
+        assert_eq!(pads.poll("Two", 0.4), presses(&[("PadSouth", false), ("PadStickUp", false)]));
+        events.borrow_mut().push_back(PadEvent::Button(0, "South".to_string(), true));
+        assert_eq!(pads.poll("off", 0.4), presses(&[]));
+    }
+}
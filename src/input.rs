@@ -0,0 +1,111 @@
+use settings::Action;
+
+/// One button's state for the current frame, worked out from key and pad events once per frame so it doesn't
+/// depend on OS key repeat
+#[derive(Default, Clone, Copy)]
+pub struct Button {
+    /// Whether any key or pad button bound to it is down
+    down: bool,
+    /// Set by any press since the last update so taps shorter than a frame still count
+    tapped: bool,
+    /// Went down this frame
+    pub pressed: bool,
+    pub held: bool,
+    /// Came up this frame
+    pub released: bool,
+    /// Frames the button has been held for, counting this one
+    pub frames: u32,
+}
+
+impl Button {
+    fn set(&mut self, down: bool, pressed: bool) {
+        self.down = down;
+        if pressed {
+            self.tapped = true;
+        }
+    }
+
+    fn update(&mut self) {
+        let held = self.down || self.tapped;
+        self.pressed = held && !self.held;
+        self.released = !held && self.held;
+        self.held = held;
+        self.frames = if held { self.frames + 1 } else { 0 };
+        self.tapped = false;
+    }
+
+    /// Pressed this frame, or held long enough to start repeating like a menu cursor should
+    pub fn repeat(&self) -> bool {
+        self.pressed || (self.frames > 20 && self.frames % 6 == 0)
+    }
+}
+
+#[derive(Default)]
+pub struct Input {
+    pub left: Button,
+    pub right: Button,
+    pub up: Button,
+    pub down: Button,
+    pub b: Button,
+    pub a: Button,
+    pub start: Button,
+    /// Keys and pad buttons held for each action, so letting go of one doesn't release another still down
+    sources: Vec<(Action, String)>,
+}
+
+impl Input {
+    /// `source` is the key or pad button the event came from
+    pub fn set(&mut self, action: Action, source: &str, state: bool) {
+        if state {
+            if !self.sources.iter().any(|held| held.0 == action && held.1 == source) {
+                self.sources.push((action, source.to_string()));
+            }
+        } else {
+            self.sources.retain(|held| held.0 != action || held.1 != source);
+        }
+        let down = self.sources.iter().any(|held| held.0 == action);
+        self.button(action).set(down, state);
+    }
+
+    fn button(&mut self, action: Action) -> &mut Button {
+        match action {
+            Action::Up => &mut self.up,
+            Action::Down => &mut self.down,
+            Action::Left => &mut self.left,
+            Action::Right => &mut self.right,
+            Action::A => &mut self.a,
+            Action::B => &mut self.b,
+            Action::Start => &mut self.start,
+        }
+    }
+
+    /// Moves every button on to the next frame, called once before each step
+    pub fn update(&mut self) {
+        for button in &mut [&mut self.left, &mut self.right, &mut self.up, &mut self.down, &mut self.b, &mut self.a, &mut self.start] {
+            button.update();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use settings::Action;
+    use super::Input;
+
+    #[test]
+    fn overlapping_sources() {
+        let mut input = Input::default();
+        input.set(Action::Left, "Left", true);
+        input.set(Action::Left, "PadDPadLeft", true);
+        input.update();
+        assert!(input.left.pressed && input.left.held);
+        // Key repeat sends the press again
+        input.set(Action::Left, "Left", true);
+        input.set(Action::Left, "Left", false);
+        input.update();
+        assert!(input.left.held && !input.left.released);
+        input.set(Action::Left, "PadDPadLeft", false);
+        input.update();
+        assert!(!input.left.held && input.left.released);
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+/// One `lang/<code>` file of `key,text` lines
+pub struct Language {
+    pub code: String,
+    strings: HashMap<String, String>,
+}
+
+/// Every loaded language, English first so it can stand in for missing strings
+pub struct Strings {
+    languages: Vec<Language>,
+    pub current: usize,
+}
+
+impl Strings {
+    pub fn load(data: &HashMap<String, String>) -> Strings {
+        let mut languages = Vec::new();
+        for (name, content) in data {
+            if !name.starts_with("lang/") {
+                continue;
+            }
+            let mut strings = HashMap::new();
+            for line in content.lines() {
+                if let Some(split) = line.find(',') {
+                    strings.insert(line[..split].trim().to_string(), line[split + 1..].trim().to_string());
+                }
+            }
+            languages.push(Language {
+                code: name[5..].to_string(),
+                strings: strings,
+            });
+        }
+        languages.sort_by(|a, b| (a.code != "en", &a.code).cmp(&(b.code != "en", &b.code)));
+        Strings {
+            languages: languages,
+            current: 0,
+        }
+    }
+
+    /// The text for `key` in the current language, falling back to English and then the key itself
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        for language in self.languages[self.current..].iter().take(1).chain(self.languages.iter().take(1)) {
+            if let Some(text) = language.strings.get(key) {
+                return text;
+            }
+        }
+        key
+    }
+
+    /// Text from a level file, where `@key` refers to the string table and anything else is shown as written
+    pub fn text<'a>(&'a self, text: &'a str) -> &'a str {
+        if text.starts_with('@') {
+            self.get(&text[1..])
+        } else {
+            text
+        }
+    }
+
+    pub fn set_language(&mut self, code: &str) {
+        if let Some(index) = self.languages.iter().position(|language| language.code == code) {
+            self.current = index;
+        }
+    }
+
+    pub fn next_language(&mut self) {
+        if !self.languages.is_empty() {
+            self.current = (self.current + 1) % self.languages.len();
+        }
+    }
+
+    /// The current language's code, English if there are no languages at all
+    pub fn code(&self) -> &str {
+        self.languages.get(self.current).map_or("en", |language| language.code.as_ref())
+    }
+
+    /// Describes every key that some language has and another is missing
+    #[cfg(test)]
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for language in &self.languages {
+            for other in &self.languages {
+                let mut missing: Vec<&String> = other.strings.keys().filter(|key| !language.strings.contains_key(*key)).collect();
+                missing.sort();
+                for key in missing {
+                    problems.push(format!("Language {} is missing {} from {}", language.code, key, other.code));
+                }
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use super::Strings;
+
+    #[test]
+    fn every_language_has_every_key() {
+        let mut data = HashMap::new();
+        for entry in fs::read_dir("assets/lang").unwrap() {
+            let path = entry.unwrap().path();
+            let mut content = String::new();
+            File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+            data.insert(format!("lang/{}", path.file_stem().unwrap().to_str().unwrap()), content);
+        }
+        let strings = Strings::load(&data);
+        assert!(strings.languages.len() > 1);
+        assert_eq!(strings.check(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn code_without_languages() {
+        assert_eq!(Strings::load(&HashMap::new()).code(), "en");
+    }
+}
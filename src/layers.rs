@@ -0,0 +1,61 @@
+use atlas::Image;
+
+/// What a sprite is drawn in front of, back to front
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layer {
+    FarBackground,
+    BackgroundTiles,
+    Entities,
+    /// Tiles like grass or gravestones that the player walks behind
+    ForegroundTiles,
+    Particles,
+    /// Transition fades, which cover the level but never the HUD
+    Fade,
+    Hud,
+    /// Text boxes and menus
+    Overlay,
+}
+
+impl Layer {
+    pub fn parse(name: &str) -> Option<Layer> {
+        match name {
+            "farbackground" => Some(Layer::FarBackground),
+            "background" => Some(Layer::BackgroundTiles),
+            "entities" => Some(Layer::Entities),
+            "foreground" => Some(Layer::ForegroundTiles),
+            "particles" => Some(Layer::Particles),
+            "fade" => Some(Layer::Fade),
+            "hud" => Some(Layer::Hud),
+            "overlay" => Some(Layer::Overlay),
+            _ => None,
+        }
+    }
+}
+
+/// Sprites sorted into layers as they're added, so the order they're added in only matters within a layer
+pub struct Layers {
+    layers: [Vec<(Image, [i32; 2], bool)>; 8],
+}
+
+impl Layers {
+    pub fn new() -> Layers {
+        Layers { layers: [Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new()] }
+    }
+
+    pub fn push(&mut self, layer: Layer, sprite: (Image, [i32; 2], bool)) {
+        self.layers[layer as usize].push(sprite);
+    }
+
+    pub fn append(&mut self, layer: Layer, sprites: &mut Vec<(Image, [i32; 2], bool)>) {
+        self.layers[layer as usize].append(sprites);
+    }
+
+    /// Everything in draw order
+    pub fn into_sprites(mut self) -> Vec<(Image, [i32; 2], bool)> {
+        let mut sprites = Vec::new();
+        for layer in self.layers.iter_mut() {
+            sprites.append(layer);
+        }
+        sprites
+    }
+}
@@ -0,0 +1,23 @@
+#version 140
+
+in vec2 screen_coord;
+out vec4 color;
+
+uniform sampler2D screen;
+// The same frame drawn with the palette being faded to
+uniform sampler2D target;
+// What this returned last frame
+uniform sampler2D previous;
+uniform float fade;
+uniform float ghosting;
+
+const int bayer[16] = int[16](0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5);
+
+void main() {
+	ivec2 pixel = ivec2(screen_coord);
+	color = texelFetch(screen, pixel, 0);
+	if ((float(bayer[(pixel.y & 3) * 4 + (pixel.x & 3)]) + 0.5) / 16.0 < fade) {
+		color = texelFetch(target, pixel, 0);
+	}
+	color = mix(color, texelFetch(previous, pixel, 0), ghosting);
+}
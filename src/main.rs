@@ -4,15 +4,67 @@
 extern crate glium;
 extern crate gif;
 //extern crate rodio;
+extern crate gilrs;
+extern crate png;
+mod animation;
+mod atlas;
+mod camera;
+mod capture;
+mod data;
+mod effects;
+mod font;
+mod gamepad;
+mod input;
+mod lang;
+mod layers;
+mod movement;
+mod palette;
+mod particles;
+mod settings;
+mod transition;
+use animation::{Mode, SpriteEvent, SpriteSheet};
+use atlas::{AtlasBuilder, Image};
+use camera::{Camera, CameraSettings};
+use capture::Capture;
+use font::Font;
+use gamepad::{Gamepads, GilrsSource, NoPads, PadSource};
+use input::Input;
+use lang::Strings;
+use layers::{Layer, Layers};
+use settings::{Action, Settings, ACTIONS};
+use movement::MovementProfile;
+use palette::PalettePair;
+use particles::{Emitter, GameEvent, Particles};
+use transition::{TransitionEvent, Transitions, Trigger};
 const LIFE_PALETTE: [[u8; 3]; 4] = [[0x23, 0x07, 0x03], [0x6d, 0x57, 0x1e], [0x9a, 0xc1, 0x6e], [0xd7, 0xf4, 0xd9]];
 const DEATH_PALETTE: [[u8; 3]; 4] = [[0x03, 0x1b, 0x1e], [0x1f, 0x2a, 0x54], [0x90, 0x70, 0xa3], [0xea, 0xd7, 0xe4]];
+/// A window at the chosen scale, or covering the main monitor
+fn window_builder(settings: &Settings) -> glium::glutin::WindowBuilder<'static> {
+    let builder = glium::glutin::WindowBuilder::new().with_title(format!("gbjam5"));
+    if settings.fullscreen {
+        builder.with_fullscreen(glium::glutin::get_primary_monitor())
+    } else {
+        builder.with_dimensions(160 * settings.scale, 144 * settings.scale)
+    }
+}
+
 fn main() {
     use glium::{DisplayBuild, Surface};
-    let display = glium::glutin::WindowBuilder::new()
-        .with_dimensions(160, 144)
-        .with_title(format!("gbjam5"))
-        .build_glium()
-        .unwrap();
+    let settings = Settings::load();
+    let mut window = (settings.scale, settings.fullscreen);
+    let display = window_builder(&settings).build_glium().unwrap();
+    // Everything is drawn at the Game Boy's resolution and then scaled up to the window. Like the palettes these are
+    // sRGB, so the cleared background isn't encoded twice and reading them back gives the palette's own colours
+    let screen = glium::texture::SrgbTexture2d::empty(&display, 160, 144).unwrap();
+    // The same frame again with the palette being dithered into
+    let fade_screen = glium::texture::SrgbTexture2d::empty(&display, 160, 144).unwrap();
+    // Dithered and ghosted frames, swapping each frame so the last one can be blended in
+    let lcd = [glium::texture::SrgbTexture2d::empty(&display, 160, 144).unwrap(),
+               glium::texture::SrgbTexture2d::empty(&display, 160, 144).unwrap()];
+    for texture in &lcd {
+        texture.as_surface().clear_color(0.0, 0.0, 0.0, 1.0);
+    }
+    let mut lcd_index = 0;
 
     let program = program!(&display,
     140 => {
@@ -20,67 +72,195 @@ fn main() {
         fragment: include_str!("sprite.frag"),
     })
         .unwrap();
+    let lcd_program = program!(&display,
+    140 => {
+        vertex: include_str!("post.vert"),
+        fragment: include_str!("lcd.frag"),
+    })
+        .unwrap();
+    let post_program = program!(&display,
+    140 => {
+        vertex: include_str!("post.vert"),
+        fragment: include_str!("post.frag"),
+    })
+        .unwrap();
     let indices = glium::index::NoIndices(glium::index::PrimitiveType::TriangleStrip);
+    let sprite_indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
     let vertex_buffer = glium::VertexBuffer::immutable(&display,
                                                        &vec![Vertex { position: [0.0, 0.0] }, Vertex { position: [1.0, 0.0] }, Vertex { position: [0.0, 1.0] }, Vertex { position: [1.0, 1.0] }])
         .unwrap();
-    let (expanded, mut bg) = expand_palettes([&LIFE_PALETTE, &DEATH_PALETTE]);
+    let (mut expanded, mut bg) = expand_palettes([&LIFE_PALETTE, &DEATH_PALETTE]);
     let mut palettes = Vec::new();
     for palette in &expanded {
         palettes.push(glium::texture::SrgbTexture1d::new(&display, palette.clone()).unwrap());
     }
     let params = glium::DrawParameters { blend: glium::Blend::alpha_blending(), ..Default::default() };
     let step_time = Duration::from_millis(20);
-    let mut game = Game::load(&display);
-    let mut input = Default::default();
+    let mut game = Game::load(&display, settings);
+    let mut input = Input::default();
+    let mut capture = Capture::new();
+    let mut screenshot = false;
+    // Last captured frame, for ghosting
+    let mut previous_pixels: Option<effects::Pixels> = None;
+    // Hotkeys only act on the first press, not on OS key repeat
+    let mut hotkeys_held = Vec::new();
     loop {
         let instant = Instant::now();
-        let mut target = display.draw();
+        input.update();
         let sprites = game.step(&input);
+        if game.quit {
+            return;
+        }
+        if (game.settings.scale, game.settings.fullscreen) != window {
+            window = (game.settings.scale, game.settings.fullscreen);
+            window_builder(&game.settings).rebuild_glium(&display).unwrap();
+        }
         let palette = game.palette_id;
         if game.palette_changed {
-            let (expanded, new_bg) = expand_palettes([&game.palettes[0], &game.palettes[1]]);
+            let (new_expanded, new_bg) = expand_palettes([&game.palettes[0], &game.palettes[1]]);
+            expanded = new_expanded;
             bg = new_bg;
             palettes.clear();
             for palette in &expanded {
                 palettes.push(glium::texture::SrgbTexture1d::new(&display, palette.clone()).unwrap());
             }
+            game.palette_changed = false;
+        }
+        let fade = if game.settings.dither { game.palette_fade } else { None };
+        let ghosting = if game.settings.ghosting { effects::GHOSTING } else { 0.0 };
+        {
+            // Every sprite on screen goes in one vertex buffer and is drawn from the atlas in a single call
+            let vertices = atlas::vertices(&sprites);
+            let sprite_buffer = if vertices.is_empty() { None } else { Some(glium::VertexBuffer::new(&display, &vertices).unwrap()) };
+            let atlas = &game.atlas;
+            let draw_screen = |surface: &mut glium::framebuffer::SimpleFrameBuffer, palette: usize| {
+                surface.clear_color_srgb(bg[palette].0, bg[palette].1, bg[palette].2, 0.0);
+                if let Some(ref sprite_buffer) = sprite_buffer {
+                    let uniforms = uniform! {
+                        atlas: atlas,
+                        palette: &palettes[palette],
+                    };
+                    surface.draw(sprite_buffer, &sprite_indices, &program, &uniforms, &params)
+                        .unwrap();
+                }
+            };
+            draw_screen(&mut screen.as_surface(), palette);
+            if let Some((to, _)) = fade {
+                draw_screen(&mut fade_screen.as_surface(), to);
+            }
+        }
+        lcd_index ^= 1;
+        {
+            let uniforms = uniform! {
+                screen: &screen,
+                target: &fade_screen,
+                previous: &lcd[lcd_index ^ 1],
+                fade: fade.map_or(0.0, |fade| fade.1),
+                ghosting: ghosting,
+            };
+            lcd[lcd_index].as_surface().draw(&vertex_buffer, &indices, &lcd_program, &uniforms, &Default::default()).unwrap();
+        }
+        if screenshot || capture.recording() {
+            let mut pixels: effects::Pixels = screen.read();
+            let mut colours = capture_colours(&expanded[palette], bg[palette]);
+            if let Some((to, amount)) = fade {
+                effects::dither(&mut pixels, &fade_screen.read(), amount);
+                colours.append(&mut capture_colours(&expanded[to], bg[to]));
+            }
+            if let Some(ref previous) = previous_pixels {
+                effects::ghost(&mut pixels, previous, ghosting);
+            }
+            // A running recording keeps the scale it started at
+            let factor = capture.recording_scale().unwrap_or(effects::capture_scale(game.settings.lcd_grid, game.settings.scanlines));
+            let mut scaled = effects::scale(&pixels, factor);
+            if game.settings.lcd_grid {
+                effects::grid(&mut scaled, factor, effects::GRID);
+            }
+            if game.settings.scanlines {
+                effects::scanlines(&mut scaled, effects::SCANLINES);
+            }
+            if screenshot {
+                if let Err(error) = capture.screenshot(&scaled, &colours) {
+                    println!("Couldn't save screenshot: {}", error);
+                }
+                screenshot = false;
+            }
+            if let Err(error) = capture.frame(&scaled, &colours) {
+                println!("Recording stopped: {}", error);
+            }
+            previous_pixels = Some(pixels);
+        } else {
+            previous_pixels = None;
         }
-        target.clear_color_srgb(bg[palette].0, bg[palette].1, bg[palette].2, 0.0);
-        for texture in sprites {
+        // Scale by the largest whole number that fits and letterbox the rest
+        let mut target = display.draw();
+        target.clear_color(0.0, 0.0, 0.0, 1.0);
+        let (width, height) = target.get_dimensions();
+        let factor = std::cmp::max(1, std::cmp::min(width / 160, height / 144));
+        {
             let uniforms = uniform! {
-                tex: texture.0.sampled(),
-                palette: &palettes[palette],
-                offset: [texture.1[0] as f32, texture.1[1] as f32],
-                flip: texture.2
+                lcd: &lcd[lcd_index],
+                factor: factor as f32,
+                grid: if game.settings.lcd_grid { effects::GRID } else { 0.0 },
+                scanlines: if game.settings.scanlines { effects::SCANLINES } else { 0.0 },
+            };
+            let params = glium::DrawParameters {
+                viewport: Some(glium::Rect {
+                    left: width.saturating_sub(160 * factor) / 2,
+                    bottom: height.saturating_sub(144 * factor) / 2,
+                    width: 160 * factor,
+                    height: 144 * factor,
+                }),
+                ..Default::default()
             };
-            target.draw(&vertex_buffer, &indices, &program, &uniforms, &params)
-                .unwrap();
+            target.draw(&vertex_buffer, &indices, &post_program, &uniforms, &params).unwrap();
         }
         target.finish().unwrap();
-        input.start = false;
-        input.a = false;
-        input.b = false;
         for ev in display.poll_events() {
             match ev {
                 glium::glutin::Event::Closed => return,
                 glium::glutin::Event::KeyboardInput(state, _, code) => {
                     let state = state == glium::glutin::ElementState::Pressed;
-                    use glium::glutin::VirtualKeyCode::*;
-                    match code {
-                        Some(Z) => input.b = state,
-                        Some(X) => input.a = state,
-                        Some(Left) => input.left = state,
-                        Some(Right) => input.right = state,
-                        Some(Up) => input.up = state,
-                        Some(Down) => input.down = state,
-                        Some(Return) => input.start = state,
-                        _ => (),
+                    if let Some(code) = code {
+                        let key = format!("{:?}", code);
+                        if state && game.key_pressed(&key) {
+                            continue;
+                        }
+                        if !state {
+                            hotkeys_held.retain(|held| *held != code);
+                        } else if !hotkeys_held.contains(&code) {
+                            hotkeys_held.push(code);
+                            match code {
+                                glium::glutin::VirtualKeyCode::F11 => {
+                                    game.settings.fullscreen = !game.settings.fullscreen;
+                                    game.settings.save();
+                                }
+                                glium::glutin::VirtualKeyCode::F12 => screenshot = true,
+                                glium::glutin::VirtualKeyCode::F10 => {
+                                    if let Err(error) = capture.toggle_recording(effects::capture_scale(game.settings.lcd_grid, game.settings.scanlines)) {
+                                        println!("Couldn't start recording: {}", error);
+                                    }
+                                }
+                                _ => (),
+                            }
+                        }
+                        for action in game.settings.actions(&key) {
+                            input.set(action, &key, state);
+                        }
                     }
                 }
                 _ => (),
             }
         }
+        let deadzone = game.settings.deadzone;
+        for (button, state) in game.gamepads.poll(&game.settings.gamepad, deadzone) {
+            if state && game.key_pressed(&button) {
+                continue;
+            }
+            for action in game.settings.actions(&button) {
+                input.set(action, &button, state);
+            }
+        }
         let elapsed = instant.elapsed();
         if step_time > elapsed {
             std::thread::sleep(step_time - elapsed);
@@ -88,6 +268,13 @@ fn main() {
     }
 }
 
+/// The colours a captured frame can contain, the background last
+fn capture_colours(palette: &[(u8, u8, u8, u8)], bg: (f32, f32, f32)) -> Vec<[u8; 3]> {
+    let mut colours: Vec<[u8; 3]> = palette.iter().filter(|colour| colour.3 > 0).map(|colour| [colour.0, colour.1, colour.2]).collect();
+    colours.push([(bg.0 * 256.0) as u8, (bg.1 * 256.0) as u8, (bg.2 * 256.0) as u8]);
+    colours
+}
+
 /// Expands the palettes and creates the transition palettes
 /// L0 L1 L2 L3 T
 /// D0 D1 D2 D3 T
@@ -119,43 +306,114 @@ fn expand_palettes(palettes: [&[[u8; 3]; 4]; 2]) -> ([(Vec<(u8, u8, u8, u8)>); 7
      [clear_colors[0], clear_colors[1], clear_colors[0], clear_colors[0], clear_colors[1], clear_colors[1], clear_colors[1]])
 }
 
-#[derive(Default)]
-struct Input {
-    left: bool,
-    right: bool,
-    up: bool,
-    down: bool,
-    b: bool,
-    a: bool,
-    start: bool,
-}
-
 use std::collections::HashMap;
 use std::sync::mpsc::{Sender,channel};
 
 struct Game {
+    /// Every image the game draws, packed while loading
+    atlas: Texture2d,
     textures: HashMap<String, Texture>,
-    font: HashMap<char, Sprite>,
+    font: Font,
+    strings: Strings,
+    hud_bar: Image,
+    settings: Settings,
+    /// Action waiting for a key press on the controls screen
+    rebinding: Option<Action>,
+    gamepads: Gamepads,
+    menu_panel: Image,
+    text_box: Image,
+    menu_icon: Sprite,
+    title: Vec<Sprite>,
+    /// Open menus, the last one gets the input
+    menus: Vec<Menu>,
+    quit: bool,
     state: GameState,
     levels: HashMap<String, String>,
+    level_order: Vec<String>,
+    config: Config,
     palette_id: usize,
+    /// Palette being dithered into and how far along, during transitions
+    palette_fade: Option<(usize, f32)>,
     palettes: [[[u8; 3]; 4]; 2],
+    /// Set when `palettes` changes so the palette textures get rebuilt
     palette_changed: bool,
+    palette_pairs: Vec<PalettePair>,
     music: Sender<f32>,
 }
 
+/// Tuning loaded from data files that every level is built with
+struct Config {
+    movement: [MovementProfile; 2],
+    camera: CameraSettings,
+    transitions: Transitions,
+    particles: HashMap<GameEvent, Vec<Emitter>>,
+}
+
 enum GameState {
-    Menu(Menu),
+    Title,
     Level(Level),
 }
 
+/// A parallax layer, `scroll` is how far it moves for each pixel the camera moves
+struct Background {
+    sprite: Sprite,
+    scroll: [f32; 2],
+    repeat: [bool; 2],
+    /// Pixels per frame the layer drifts by on its own
+    autoscroll: [f32; 2],
+    offset: [f32; 2],
+    versions: [bool; 2],
+}
+
 struct Menu {
-    selection: u8,
+    kind: MenuKind,
+    selection: usize,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum MenuKind {
+    Title,
+    Pause,
+    LevelSelect,
+    Options,
+    Palettes,
+    Effects,
+    Controls,
+}
+
+impl Menu {
+    fn new(kind: MenuKind) -> Menu {
+        Menu {
+            kind: kind,
+            selection: 0,
+        }
+    }
+
+    /// Moves the selection with the d-pad, returns the entry picked with A or start
+    fn step(&mut self, input: &Input, entries: usize) -> Option<usize> {
+        if input.up.repeat() {
+            self.selection = (self.selection + entries - 1) % entries;
+        }
+        if input.down.repeat() {
+            self.selection = (self.selection + 1) % entries;
+        }
+        if input.a.pressed || input.start.pressed {
+            Some(self.selection)
+        } else {
+            None
+        }
+    }
 }
 
 type TileMap = Vec<Vec<Rc<Tile>>>;
 
 struct Level {
+    /// Key in `levels`, used to restart it
+    id: String,
+    /// Shown in the HUD, may be an `@key` into the string table
+    name: String,
+    /// Frames played so far, not counting pauses and transitions
+    frames: u32,
     tile_map: [TileMap; 2],
     tile_sprites: Vec<Sprite>,
     entities: Vec<Entity>,
@@ -163,19 +421,64 @@ struct Level {
     width: i32,
     height: i32,
     version: usize,
-    backgrounds: Vec<Sprite>,
+    backgrounds: Vec<Background>,
     key_count: u8,
     keys_collected: u8,
     paused: bool,
-    pause_sprites: [Vec<(Rc<Texture2d>, [i32; 2], bool)>; 2],
-    fade: [Rc<Texture2d>; 5],
-    switch: i8
+    pause_sprites: [Vec<(Layer, Image, [i32; 2], bool)>; 2],
+    fade: Vec<Image>,
+    /// The transition being played and how many frames into it
+    transition: Option<(Trigger, u32)>,
+    camera: Camera,
+    dialogue: Option<Dialogue>,
+    particles: Particles,
+}
+
+/// A message being typed out in a text box, one page at a time
+struct Dialogue {
+    pages: Vec<String>,
+    page: usize,
+    /// Characters of the current page revealed so far
+    shown: usize,
+}
+
+impl Dialogue {
+    /// Pages are separated by `|`
+    fn new(message: &str) -> Dialogue {
+        Dialogue {
+            pages: message.split('|').map(|page| page.trim().to_string()).collect(),
+            page: 0,
+            shown: 0,
+        }
+    }
+
+    fn step(&mut self) {
+        if self.shown < self.pages[self.page].chars().count() {
+            self.shown += 1;
+        }
+    }
+
+    /// Reveals the rest of the page or moves to the next one, returns true once past the last page
+    fn advance(&mut self) -> bool {
+        let length = self.pages[self.page].chars().count();
+        if self.shown < length {
+            self.shown = length;
+        } else {
+            self.page += 1;
+            self.shown = 0;
+        }
+        self.page >= self.pages.len()
+    }
 }
 
 #[derive(Debug)]
 struct Tile {
     sprite_id: usize,
     tile_type: TileType,
+    /// Height of the solid part at the left and right edges, slopes run straight between them
+    shape: [i32; 2],
+    /// Either background tiles behind entities or foreground tiles in front of them
+    layer: Layer,
 }
 
 impl Tile {
@@ -189,6 +492,56 @@ impl Tile {
             TileType::Solid => true,
             TileType::SwitchBlock => true,
             TileType::Arrow(_) => false,
+            TileType::PhaseBlock => true,
+            TileType::PushBlock => true,
+            TileType::Platform => false,
+            TileType::Ladder => false,
+            TileType::Spikes => false,
+        }
+    }
+
+    /// Height of the solid part in a column of the tile
+    fn height(&self, x: i32) -> i32 {
+        let (left, right) = (self.shape[0], self.shape[1]);
+        if right > left {
+            left + (right - left) * (x + 1) / 16
+        } else {
+            right + (left - right) * (16 - x) / 16
+        }
+    }
+
+    /// A named shape or `left:right` heights, `None` for anything else
+    fn parse_shape(shape: &str) -> Option<[i32; 2]> {
+        match shape.trim().to_lowercase().as_ref() {
+            "full" => Some([16, 16]),
+            "half" => Some([8, 8]),
+            "slope45_up" => Some([0, 16]),
+            "slope45_down" => Some([16, 0]),
+            "slope22_up_low" => Some([0, 8]),
+            "slope22_up_high" => Some([8, 16]),
+            "slope22_down_high" => Some([16, 8]),
+            "slope22_down_low" => Some([8, 0]),
+            other => {
+                other.find(':').and_then(|split| match (other[..split].parse(), other[split + 1..].parse()) {
+                    (Ok(left), Ok(right)) if left >= 0 && left <= 16 && right >= 0 && right <= 16 => Some([left, right]),
+                    _ => None,
+                })
+            }
+        }
+    }
+
+    fn is_ladder(&self) -> bool {
+        match self.tile_type {
+            TileType::Ladder => true,
+            _ => false,
+        }
+    }
+
+    /// Solidity for a form that may be able to phase through phase blocks
+    fn is_solid_for(&self, phasing: bool) -> bool {
+        match self.tile_type {
+            TileType::PhaseBlock => !phasing,
+            _ => self.is_solid(),
         }
     }
 }
@@ -203,6 +556,11 @@ enum TileType {
     Switch,
     SwitchBlock,
     Arrow(Direction),
+    PhaseBlock,
+    PushBlock,
+    Platform,
+    Ladder,
+    Spikes,
 }
 
 impl Default for TileType {
@@ -220,6 +578,8 @@ struct Entity {
     dead: bool,
     versions: [bool; 2],
     physics: bool,
+    /// The same creature's entity in the other version
+    link: Option<usize>,
     entity_type: EntityType,
 }
 
@@ -227,6 +587,8 @@ enum EntityType {
     Player(Player),
     Enemy(Enemy),
     Key(Key),
+    Platform(Platform),
+    Sign(Sign),
 }
 
 struct Enemy {
@@ -243,10 +605,59 @@ struct Key {
     distance: i32,
 }
 
+/// Opens a text box with its message when the player presses B next to it
+struct Sign {
+    sprite: Sprite,
+    message: String,
+}
+
 enum AI {
     Pace(Direction),
 }
 
+struct Platform {
+    sprite: Sprite,
+    route: Route,
+    speed: i32,
+    /// Blocks from the sides and below as well as being stood on
+    solid: bool,
+}
+
+enum Route {
+    /// Moves in a direction, turning when lined up with an arrow tile
+    Arrows(Direction),
+    /// Visits each point in turn then loops back to the first
+    Path(Vec<[i32; 2]>, usize),
+}
+
+/// Where a moving platform ended up this frame and how far it moved to get there
+struct PlatformBox {
+    x: i32,
+    y: i32,
+    dx: i32,
+    dy: i32,
+    solid: bool,
+}
+
+impl PlatformBox {
+    fn overlaps_x(&self, x: i32) -> bool {
+        x + 15 >= self.x && x <= self.x + 15
+    }
+
+    fn overlaps_y(&self, y: i32) -> bool {
+        y + 15 >= self.y && y <= self.y + 15
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x <= self.x + 15 && y >= self.y && y <= self.y + 15
+    }
+}
+
+/// Whether two x, y, width, height boxes touch
+fn overlaps(a: [i32; 4], b: [i32; 4]) -> bool {
+    a[0] < b[0] + b[2] && b[0] < a[0] + a[2] && a[1] < b[1] + b[3] && b[1] < a[1] + a[3]
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 enum Direction {
     Up,
@@ -260,6 +671,12 @@ struct Player {
     checkpoint_x: i32,
     checkpoint_y: i32,
     sprites: [PlayerSprites; 2],
+    movement: [MovementProfile; 2],
+    coyote: u8,
+    jump_buffer: u8,
+    jump_held: bool,
+    air_jumps: u8,
+    climbing: bool,
 }
 
 struct PlayerSprites {
@@ -272,6 +689,20 @@ struct PlayerSprites {
     reviving: Sprite,
 }
 
+impl PlayerSprites {
+    fn get(&mut self, state: &PlayerState) -> &mut Sprite {
+        match *state {
+            PlayerState::Walking => &mut self.walking,
+            PlayerState::Standing | PlayerState::Climbing => &mut self.standing,
+            PlayerState::Falling => &mut self.falling,
+            PlayerState::Jumping => &mut self.jumping,
+            PlayerState::Dying => &mut self.dying,
+            PlayerState::Turning(_) => &mut self.turning,
+            PlayerState::Reviving => &mut self.reviving,
+        }
+    }
+}
+
 #[derive(Eq, PartialEq)]
 enum PlayerState {
     Walking,
@@ -281,6 +712,7 @@ enum PlayerState {
     Dying,
     Turning(String),
     Reviving,
+    Climbing,
 }
 
 /*
@@ -331,31 +763,72 @@ impl<R> Iterator for MixSource<R> where R: Read + Seek {
 use rodio::Source;*/
 
 impl Game {
-    pub fn load<F>(facade: &F) -> Game
+    pub fn load<F>(facade: &F, settings: Settings) -> Game
         where F: glium::backend::Facade
     {
         let mut textures = HashMap::new();
         let mut levels = HashMap::new();
         let mut sounds = HashMap::new();
+        let mut data = HashMap::new();
+        let mut images = HashMap::new();
+        let mut palette_images = HashMap::new();
+        let mut gifs = Vec::new();
+        let mut atlas = AtlasBuilder::new();
         for (name, content) in embed!("assets") {
             let name = String::from_utf8(name).unwrap().replace(r"\", "/");
-            if name.ends_with(".gif") {
-                textures.insert(name[..name.len() - 4].to_string(),
-                                Sprite::load(facade, &content));
+            if name.starts_with("palettes/") && name.ends_with(".gif") {
+                palette_images.insert(name[..name.len() - 4].to_string(), content);
+            } else if name.ends_with(".gif") {
+                gifs.push((name[..name.len() - 4].to_string(), content));
             } else if name.starts_with("levels/") {
                 levels.insert(name[7..name.len() - 4].to_string(),
                               String::from_utf8(content).unwrap());
             } else if name.ends_with(".ogg") || name.ends_with(".wav") {
                 sounds.insert(name[..name.len() - 4].to_string(), content);
+            } else if name.ends_with(".png") {
+                images.insert(name[..name.len() - 4].to_string(), content);
+            } else if name.ends_with(".txt") {
+                data.insert(name[..name.len() - 4].to_string(),
+                            String::from_utf8(content).unwrap());
             }
         }
-
-        let mut font = HashMap::new();
-        let chars = ['A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '.', '!', '?', ',', '~', '"', '\''];
-        for i in 0..chars.len() {
-            font.insert(chars[i],
-                        Sprite::new(textures.get(&format!("GUI/font_{}", i)).unwrap()));
+        // Sprites wait for every text file so each can pick up its sidecar
+        for (name, content) in gifs {
+            let texture = Sprite::load(&mut atlas, &name, &content, data.get(&name).map(|sidecar| sidecar.as_ref()));
+            textures.insert(name, texture);
         }
+        let config = Config {
+            movement: match data.get("player/movement") {
+                Some(string) => MovementProfile::parse(string),
+                None => [MovementProfile::default(), MovementProfile::default()],
+            },
+            camera: match data.get("camera") {
+                Some(string) => CameraSettings::parse(string),
+                None => CameraSettings::default(),
+            },
+            transitions: Transitions::load(&data),
+            particles: particles::load(data.get("particles").unwrap(), &textures),
+        };
+
+        let font = Font::load(&mut atlas, data.get("GUI/Font").unwrap(), &images);
+        let mut strings = Strings::load(&data);
+        strings.set_language(&settings.language);
+        let palette_pairs = palette::load(&data, &palette_images);
+        let palettes = match palette_pairs.iter().find(|pair| pair.id == settings.palette) {
+            Some(pair) => pair.colours,
+            None => [LIFE_PALETTE, DEATH_PALETTE],
+        };
+        let menu_panel = atlas.add(156, 80, Game::panel(156, 80));
+        let text_box = atlas.add(156, 44, Game::panel(156, 44));
+        let hud_bar = atlas.add(160, 10, Game::panel(160, 10));
+        let menu_icon = Sprite::new(textures.get("GUI/MenuSelectIcon").unwrap());
+        let title = (0..3).map(|i| Sprite::new(textures.get(&format!("GUI/LifeTitle_{}", i)).unwrap())).collect();
+        let level_order = match levels.get("Level Order") {
+            Some(order) => {
+                order.lines().skip(1).map(|line| line.trim().to_string()).filter(|line| !line.is_empty()).collect()
+            }
+            None => vec!["Tutorial_Level".to_string()],
+        };
         let (sender, reciever) = channel();
 
         /*let music = {
@@ -392,15 +865,45 @@ impl Game {
             palette_changed: true,
             textures: textures,
             levels: levels,
-            state: GameState::Menu(Menu { selection: 0 }),
+            config: config,
+            menu_panel: menu_panel,
+            text_box: text_box,
+            menu_icon: menu_icon,
+            title: title,
+            menus: vec![Menu::new(MenuKind::Title)],
+            quit: false,
+            level_order: level_order,
+            state: GameState::Title,
             palette_id: 0,
-            palettes: [LIFE_PALETTE, DEATH_PALETTE],
+            palette_fade: None,
+            palettes: palettes,
+            palette_pairs: palette_pairs,
             font: font,
+            strings: strings,
+            hud_bar: hud_bar,
+            atlas: atlas.build(facade),
+            settings: settings,
+            rebinding: None,
+            gamepads: Gamepads::new(match GilrsSource::new() {
+                Some(source) => Box::new(source) as Box<PadSource>,
+                None => Box::new(NoPads),
+            }),
             music: sender,
         }
     }
 
-    fn load_level(textures: &HashMap<String, Texture>, string: &str) -> Level {
+    /// Builds a box with a dark border and light fill to draw text on top of
+    fn panel(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                data.push(if x == 0 || y == 0 || x == width - 1 || y == height - 1 { 0 } else { 3 });
+            }
+        }
+        data
+    }
+
+    fn load_level(textures: &HashMap<String, Texture>, config: &Config, name: &str, string: &str) -> Level {
         let mut lines = string.lines();
         let mut tile_mappings = HashMap::new();
         let mut entity_mappings = HashMap::new();
@@ -412,6 +915,8 @@ impl Game {
                              Rc::new(Tile {
                                  sprite_id: 0,
                                  tile_type: TileType::Background,
+                                 shape: [16, 16],
+                                 layer: Layer::BackgroundTiles,
                              }));
         while let Some(line) = lines.next() {
             if line == "ENTITY" {
@@ -430,7 +935,7 @@ impl Game {
                         }
                     })
                 };
-            let tile = Tile {
+            let mut tile = Tile {
                 sprite_id: sprite_id,
                 tile_type: match values.next().unwrap().to_lowercase().as_ref() {
                     "background" => TileType::Background,
@@ -465,6 +970,11 @@ impl Game {
                     "checkpoint" => TileType::Checkpoint,
                     "switch" => TileType::Switch,
                     "switchblock" => TileType::SwitchBlock,
+                    "phaseblock" => TileType::PhaseBlock,
+                    "pushblock" => TileType::PushBlock,
+                    "platform" => TileType::Platform,
+                    "ladder" => TileType::Ladder,
+                    "spikes" => TileType::Spikes,
                     "arrow" => TileType::Arrow(match values.next().unwrap().to_lowercase().as_ref() {
                                         "right" => Direction::Right,
                                         "up" => Direction::Up,
@@ -473,7 +983,19 @@ impl Game {
                                     }),
                     _ => TileType::Background,
                 },
+                shape: [16, 16],
+                layer: Layer::BackgroundTiles,
             };
+            // Anything left is the shape and the layer, in either order
+            for value in values {
+                if let Some(layer) = Layer::parse(value.trim().to_lowercase().as_ref()) {
+                    tile.layer = layer;
+                } else if let Some(shape) = Tile::parse_shape(value) {
+                    tile.shape = shape;
+                } else {
+                    data::report(&format!("levels/{}", name), line, &format!("unknown shape or layer {}", value.trim()));
+                }
+            }
             tile_mappings.insert(character, Rc::new(tile));
         }
         while let Some(line) = lines.next() {
@@ -484,15 +1006,27 @@ impl Game {
             let character = values.next().unwrap().chars().next().unwrap();
             entity_mappings.insert(character, line);
         }
-        let backgrounds = {
-            let mut backgrounds = Vec::new();
+        let mut backgrounds = {
+            let mut sprites = Vec::new();
             let prefix = lines.next().unwrap();
-            let mut num = 0;
-            while let Some(texture) = textures.get(&format!("{}_{}", prefix, num)) {
-                backgrounds.push(Sprite::new(texture));
-                num += 1;
+            while let Some(texture) = textures.get(&format!("{}_{}", prefix, sprites.len())) {
+                sprites.push(Sprite::new(texture));
             }
-            backgrounds
+            // Without a BACKGROUND section each layer scrolls faster than the one behind it
+            let layers = sprites.len() * sprites.len();
+            sprites.into_iter()
+                .enumerate()
+                .map(|(i, sprite)| {
+                    Background {
+                        sprite: sprite,
+                        scroll: [(i * i) as f32 / layers as f32, 0.0],
+                        repeat: [true, false],
+                        autoscroll: [0.0, 0.0],
+                        offset: [0.0, 0.0],
+                        versions: [true, true],
+                    }
+                })
+                .collect::<Vec<_>>()
         };
         let mut values = lines.next().unwrap().split(',');
         let width = values.next().unwrap().trim().parse().unwrap();
@@ -516,10 +1050,17 @@ impl Game {
             facing: true,
             dead: false,
             physics: true,
+            link: None,
             entity_type: EntityType::Player(Player {
                 checkpoint_x: 0,
                 checkpoint_y: 5 * 16,
                 state: PlayerState::Standing,
+                movement: config.movement,
+                coyote: 0,
+                jump_buffer: 0,
+                jump_held: false,
+                air_jumps: 0,
+                climbing: false,
                 sprites: [PlayerSprites {
                     walking: Sprite::new(&textures["player/MonsterWalk"]),
                     standing: Sprite::new(&textures["player/MonsterStand"]),
@@ -541,6 +1082,7 @@ impl Game {
             }),
         });
         let mut key_count = 0;
+        let mut links = HashMap::new();
         let mut tile_maps = Vec::new();
         for i in 0..2 {
             let mut tile_map = Vec::new();
@@ -568,6 +1110,7 @@ impl Game {
                                     dead: false,
                                     versions: versions,
                                     physics: false,
+                                    link: None,
                                     entity_type: EntityType::Key(Key {
                                         collected: false,
                                         sprite: Sprite::new(&textures["entities/Key"]),
@@ -607,6 +1150,7 @@ impl Game {
                                     dead: false,
                                     versions: versions,
                                     physics: gravity && collision,
+                                    link: None,
                                     entity_type: EntityType::Enemy(Enemy {
                                         collision: collision,
                                         gravity: gravity,
@@ -616,9 +1160,84 @@ impl Game {
                                     }),
                                 })
                             },
+                            "platform" => {
+                                let filename = values.next().unwrap();
+                                let entity_x = (width - 1 - x) * 16;
+                                let entity_y = (height - 1 - y) * 16;
+                                let mut direction = Direction::Left;
+                                let mut points = vec![[entity_x, entity_y]];
+                                let mut speed = 1;
+                                let mut solid = false;
+                                // Arguments are a starting direction for following arrows or x:y tile offsets to
+                                // visit with positive y going up, optionally followed by a speed and "solid"
+                                while let Some(arg) = values.next() {
+                                    let arg = arg.trim().to_lowercase();
+                                    match arg.as_ref() {
+                                        "solid" => solid = true,
+                                        "left" => direction = Direction::Left,
+                                        "right" => direction = Direction::Right,
+                                        "up" => direction = Direction::Up,
+                                        "down" => direction = Direction::Down,
+                                        _ => {
+                                            if let Some(split) = arg.find(':') {
+                                                let offset_x: i32 = arg[..split].parse().unwrap();
+                                                let offset_y: i32 = arg[split + 1..].parse().unwrap();
+                                                points.push([entity_x + offset_x * 16, entity_y + offset_y * 16]);
+                                            } else if let Ok(value) = arg.parse() {
+                                                speed = value;
+                                            }
+                                        }
+                                    }
+                                }
+                                Some(Entity {
+                                    x: entity_x,
+                                    y: entity_y,
+                                    x_speed: 0.0,
+                                    y_speed: 0.0,
+                                    facing: false,
+                                    dead: false,
+                                    versions: versions,
+                                    physics: false,
+                                    link: None,
+                                    entity_type: EntityType::Platform(Platform {
+                                        sprite: Sprite::new(textures.get(filename).unwrap()),
+                                        route: if points.len() > 1 {
+                                            Route::Path(points, 1)
+                                        } else {
+                                            Route::Arrows(direction)
+                                        },
+                                        speed: speed,
+                                        solid: solid,
+                                    }),
+                                })
+                            },
+                            "sign" => {
+                                let filename = values.next().unwrap();
+                                // The message is the rest of the line so it can contain commas
+                                let message = values.collect::<Vec<_>>().join(",");
+                                Some(Entity {
+                                    x: (width - 1 - x) * 16,
+                                    y: (height - 1 - y) * 16,
+                                    x_speed: 0.0,
+                                    y_speed: 0.0,
+                                    facing: false,
+                                    dead: false,
+                                    versions: versions,
+                                    physics: false,
+                                    link: None,
+                                    entity_type: EntityType::Sign(Sign {
+                                        sprite: Sprite::new(textures.get(filename).unwrap()),
+                                        message: message,
+                                    }),
+                                })
+                            },
                             _ => None,
                         };
                         entities.push(entity.unwrap());
+                        // Linked entities pair up in the order they appear in each version's map
+                        if entity_line.split(',').skip(2).any(|arg| arg.trim().to_lowercase() == "link") {
+                            links.entry(character).or_insert([Vec::new(), Vec::new()])[i].push(entities.len() - 1);
+                        }
                     }
                 }
                 tile_map.push(row);
@@ -626,17 +1245,99 @@ impl Game {
             tile_map.reverse();
             tile_maps.push(tile_map);
         }
-        let fade = {
-            let texture = &textures["Fade"];
-            [
-                texture[0].0.clone(),
-                texture[1].0.clone(),
-                texture[2].0.clone(),
-                texture[3].0.clone(),
-                texture[4].0.clone(),
-            ]
-        };
+        let mut camera = Camera::new(config.camera);
+        let mut title = name.replace('_', " ");
+        let mut section = "";
+        while let Some(line) = lines.next() {
+            match line.trim() {
+                "" => continue,
+                "CAMERA" => {
+                    section = "CAMERA";
+                    continue;
+                }
+                "BACKGROUND" => {
+                    section = "BACKGROUND";
+                    backgrounds.clear();
+                    continue;
+                }
+                "TITLE" => {
+                    section = "TITLE";
+                    continue;
+                }
+                _ => (),
+            }
+            if section == "TITLE" {
+                // Either the title itself or @key for one from the string table
+                title = line.trim().to_string();
+                continue;
+            }
+            if section == "BACKGROUND" {
+                // texture,scroll_x,scroll_y,repeat,autoscroll_x,autoscroll_y,versions
+                let values = data::values(line);
+                // Numbers left out are 0
+                let number = |i: usize| -> Result<f32, String> {
+                    match data::get(&values, i) {
+                        "" => Ok(0.0),
+                        value => data::parse(value),
+                    }
+                };
+                let repeat = match data::get(&values, 3).to_lowercase().as_ref() {
+                    "none" => [false, false],
+                    "y" => [false, true],
+                    "both" => [true, true],
+                    _ => [true, false],
+                };
+                let versions = match data::get(&values, 6).to_lowercase().as_ref() {
+                    "life" => [true, false],
+                    "death" => [false, true],
+                    _ => [true, true],
+                };
+                let background = textures.get(values[0]).ok_or(format!("no texture {}", values[0])).and_then(|texture| {
+                    Ok(Background {
+                        sprite: Sprite::new(texture),
+                        scroll: [try!(number(1)), try!(number(2))],
+                        repeat: repeat,
+                        autoscroll: [try!(number(4)), try!(number(5))],
+                        offset: [0.0, 0.0],
+                        versions: versions,
+                    })
+                });
+                match background {
+                    Ok(background) => backgrounds.push(background),
+                    Err(problem) => data::report(&format!("levels/{}", name), line, &problem),
+                }
+                continue;
+            }
+            let values = data::values(line);
+            if section == "CAMERA" {
+                let result = if values[0].to_lowercase() == "lock" {
+                    // Locks are given in tiles as they appear in the map, counting rows from the top
+                    values[1..].iter().map(|value| data::parse(value)).collect::<Result<Vec<i32>, String>>().and_then(|lock| {
+                        if lock.len() < 4 {
+                            return Err("lock needs x,y,width,height".to_string());
+                        }
+                        camera.locks.push([lock[0] * 16, (height - lock[1] - lock[3]) * 16, lock[2] * 16, lock[3] * 16]);
+                        Ok(())
+                    })
+                } else {
+                    camera.settings.set(&values[0].to_lowercase(), data::get(&values, 1))
+                };
+                if let Err(problem) = result {
+                    data::report(&format!("levels/{}", name), line, &problem);
+                }
+            }
+        }
+        for (_, link) in links {
+            for (&life, &death) in link[0].iter().zip(link[1].iter()) {
+                entities[life].link = Some(death);
+                entities[death].link = Some(life);
+            }
+        }
+        let fade = textures["Fade"].frames.iter().map(|frame| frame.0.clone()).collect();
         Level {
+            id: name.to_string(),
+            name: title,
+            frames: 0,
             tile_map: [tile_maps.remove(0), tile_maps.remove(0)],
             tile_sprites: tile_sprites,
             width: width,
@@ -650,331 +1351,454 @@ impl Game {
             paused: false,
             pause_sprites: [Vec::new(), Vec::new()],
             fade: fade,
-            switch: 0
+            transition: None,
+            camera: camera,
+            dialogue: None,
+            particles: Particles::new(),
         }
     }
 
-    fn text(&mut self, text: &str, mut x: i32, mut y: i32, width: i32) -> Vec<(Rc<Texture2d>, [i32; 2], bool)> {
+    fn text(&self, text: &str, x: i32, y: i32, width: i32) -> Vec<(Image, [i32; 2], bool)> {
+        self.typed_text(text, x, y, width, std::usize::MAX)
+    }
+
+    /// Lays out the whole text but only draws the first `shown` characters, so words don't jump lines as they type
+    fn typed_text(&self, text: &str, mut x: i32, mut y: i32, width: i32, shown: usize) -> Vec<(Image, [i32; 2], bool)> {
         let start = x;
         let mut vec = Vec::new();
+        let mut count = 0;
         for word in text.split(' ') {
-            if x + word.len() as i32 * 6 > start + width && word.len() as i32 * 6 < width {
-                x = start;
-                y -= 10
-            } else if x > start {
-                x += 6
-            }
-            for c in word.to_uppercase().chars() {
-                if self.font.contains_key(&c) {
-                    vec.push((self.font.get_mut(&c).unwrap().texture(), [x, y], false));
+            let word_width = self.font.measure(word);
+            if x > start {
+                let space = self.font.space + self.font.spacing;
+                if x + space + word_width > start + width && word_width < width {
+                    x = start;
+                    y -= self.font.line_height;
+                } else {
+                    x += space;
+                }
+            }
+            if count > 0 {
+                count += 1;
+            }
+            let mut previous = None;
+            for c in word.chars() {
+                count += 1;
+                if count > shown {
+                    return vec;
                 }
-                x += 6;
-                if x > start + width {
+                if let Some(previous) = previous {
+                    x += self.font.kerning(previous, c);
+                }
+                let glyph = self.font.glyph(c);
+                if x + glyph.width > start + width {
                     x = start;
-                    y -= 10;
+                    y -= self.font.line_height;
                 }
+                vec.push((glyph.texture.clone(), [x, y + glyph.y], false));
+                x += glyph.width + self.font.spacing;
+                previous = Some(c);
             }
         }
         vec
     }
 
-    pub fn step(&mut self, input: &Input) -> Vec<(Rc<Texture2d>, [i32; 2], bool)> {
-        let mut sprites = Vec::new();
+    /// Level name and timer along the top, form and keys along the bottom
+    fn hud(&mut self, name: &str, frames: u32, keys: [u8; 2], version: usize) -> Vec<(Image, [i32; 2], bool)> {
+        let mut sprites = vec![(self.hud_bar.clone(), [0, 134], false), (self.hud_bar.clone(), [0, 0], false)];
+        let seconds = frames / 50;
+        let timer = format!("{}'{:02}\"", seconds / 60, seconds % 60);
+        let form = self.strings.get(if version == 0 { "hud.life" } else { "hud.death" });
+        let keys = self.strings.get("hud.keys").replace("{0}", &keys[0].to_string()).replace("{1}", &keys[1].to_string());
+        let name = self.font.truncate(self.strings.text(name), 150 - self.font.measure(&timer));
+        sprites.append(&mut self.text(&name, 2, 135, 160));
+        sprites.append(&mut self.text(&timer, 158 - self.font.measure(&timer), 135, 160));
+        sprites.append(&mut self.text(form, 2, 1, 60));
+        sprites.append(&mut self.text(&keys, 158 - self.font.measure(&keys), 1, 160));
+        sprites
+    }
+
+    pub fn step(&mut self, input: &Input) -> Vec<(Image, [i32; 2], bool)> {
+        let mut sprites = Layers::new();
         let mut new_level = Option::None;
+        let mut hud = None;
+        let mut text_box = None;
+        let menu_open = !self.menus.is_empty();
+        if menu_open {
+            self.menu_step(input);
+        }
+        let mut open_pause = false;
         if let Some(new_state) = match self.state {
-            GameState::Menu(ref mut menu) => {
-                Some(GameState::Level(Game::load_level(&self.textures,
-                                                       &self.levels.get("Tutorial_Level").unwrap())))
+            GameState::Title => {
+                self.palette_id = 0;
+                self.palette_fade = None;
+                for sprite in &mut self.title {
+                    sprites.push(Layer::FarBackground, (sprite.texture(), [0, 0], false));
+                }
+                None
             }
             GameState::Level(ref mut level) => {
                 self.palette_id = level.version;
-                if input.start {
-                    level.paused = !level.paused;
-                }
-                let camera_x = if level.entities[0].x > 80 {
-                    if level.entities[0].x < level.width as i32 * 16 - 80 {
-                        level.entities[0].x - 80
-                    } else {
-                        level.width as i32 * 16 - 160
-                    }
-                } else {
-                    0
-                };
-                let camera_y = if level.entities[0].y > 72 {
-                    if level.entities[0].y < level.height as i32 * 16 - 72 {
-                        level.entities[0].y - 72
-                    } else {
-                        level.height as i32 * 16 - 144
-                    }
-                } else {
-                    0
-                };
-                self.palette_changed = false;
-                for i in 0..level.backgrounds.len() {
-                    let offset = (0 - (i * i) as i32 * camera_x / (level.backgrounds.len() * level.backgrounds.len()) as i32) % 160;
-                    sprites.push((level.backgrounds[i].texture(), [offset, 0], false));
-                    sprites.push((level.backgrounds[i].texture(), [offset + 160, 0], false));
+                self.palette_fade = None;
+                if input.start.pressed && !menu_open && !level.paused {
+                    open_pause = true;
                 }
-                let mut fade_index = 0;
-                if level.switch > 0 {
+                let mut fade_index = None;
+                // Gameplay events this frame with where they happened, for particles
+                let mut events = Vec::new();
+                if let Some((trigger, frame)) = level.transition {
                     level.paused = true;
-                    if level.switch >= 57 {
-                        self.palette_id = 2;
-                    } else if level.switch >= 48 {
-                        self.palette_id = 3;
-                    } else if level.switch >= 18 {
-                        self.palette_id = 4;
-                        fade_index = (level.switch - 18) / 6;
-                    } else if level.switch >= 9 {
-                        if level.switch == 17 {
-                            let mut player_entity = &mut level.entities[0];
-                            player_entity.dead = false;
-                            if let EntityType::Player(ref mut player) = player_entity.entity_type {
-                                player.state = PlayerState::Standing;
-                                if level.version == 0 {
-                                    while Level::get_tile(&level.tile_map[1],
-                                                          player_entity.x,
-                                                          player_entity.y,
-                                                          level.wraparound)
-                                        .is_solid() ||
-                                        Level::get_tile(&level.tile_map[1],
-                                                        player_entity.x + 15,
-                                                        player_entity.y,
-                                                        level.wraparound)
-                                            .is_solid() {
-                                        player_entity.y += 16;
-                                    }
-                                    level.version = 1;
-                                } else {
-                                    player_entity.x = player.checkpoint_x;
-                                    player_entity.y = player.checkpoint_y;
-                                    level.version = 0;
-                                }
+                    match self.config.transitions.get(trigger).at(frame) {
+                        Some((keyframe, first)) => {
+                            if first && keyframe.event == Some(TransitionEvent::Respawn) {
+                                level.respawn();
                             }
+                            self.palette_id = keyframe.palette;
+                            self.palette_fade = self.config.transitions.get(trigger).fade_to(frame);
+                            fade_index = keyframe.fade;
+                            level.transition = Some((trigger, frame + 1));
+                        }
+                        None => {
+                            level.transition = None;
+                            level.paused = false;
                         }
-                        self.palette_id = 5;
-                    } else {
-                        self.palette_id = 6;
-                    }
-                    level.switch -= 1;
-                    //0, 0 > 0.0
-                    //60, 1 > 1.0
-                    //0, 1 > 1.0
-                    //60, 1 > 0.0
-                    //let mix = if (level.switch > 17) ^ (level.version == 0) {(60 - level.switch) as f32 / 60.0} else { level.switch as f32 / 60.0 };
-                    //self.music.send(mix).unwrap();
-                    if level.switch == 0 {
-                        level.paused = false;
                     }
-                    if level.version == 0 {
-                        fade_index = 4 - fade_index;
-                        self.palette_id = 6 - (self.palette_id - 2);
+                }
+                // Closing the menu, restarting or picking a level this frame waits for the end of the step to unpause
+                if menu_open {
+                    level.paused = true;
+                }
+                let mut dialogue_closed = false;
+                if !menu_open {
+                    if let Some(ref mut dialogue) = level.dialogue {
+                        if input.a.pressed {
+                            dialogue_closed = dialogue.advance();
+                        } else {
+                            dialogue.step();
+                        }
                     }
                 }
+                if dialogue_closed {
+                    level.dialogue = None;
+                }
                 let mut relative_sprites = Vec::new();
                 if !level.paused {
-                    let textures: Vec<Rc<Texture2d>> = level.tile_sprites.iter_mut().map(|sprite| sprite.texture()).collect();
-                    for y in 0..level.height as usize {
-                        for x in 0..level.width as usize {
-                            let tile = &level.tile_map[level.version][y][x];
-                            let mut sprite_id = tile.sprite_id;
-                            if let TileType::Door(_, sprites) = tile.tile_type {
-                                sprite_id = sprites[(level.keys_collected >= level.key_count) as usize];
-                            }
-                            if let TileType::KeyBackground(sprites) = tile.tile_type {
-                                sprite_id = sprites[(level.keys_collected >= level.key_count) as usize];
-                            }
-                            if tile.sprite_id != 0 {
-                                relative_sprites.push((textures[sprite_id].clone(), [x as i32 * 16, y as i32 * 16], false))
+                    let mut platforms = Vec::new();
+                    for entity in level.entities.iter_mut() {
+                        if !entity.versions[level.version] {
+                            continue;
+                        }
+                        if let EntityType::Platform(ref mut platform) = entity.entity_type {
+                            let (old_x, old_y) = (entity.x, entity.y);
+                            let speed = platform.speed;
+                            match platform.route {
+                                Route::Arrows(ref mut direction) => {
+                                    if entity.x % 16 == 0 && entity.y % 16 == 0 {
+                                        if let TileType::Arrow(ref arrow_dir) = Level::get_tile(&level.tile_map[level.version],
+                                                                                                entity.x + 8,
+                                                                                                entity.y + 8,
+                                                                                                level.wraparound).tile_type {
+                                            *direction = *arrow_dir;
+                                        }
+                                        // With no arrow to turn it, a platform about to leave the level comes back
+                                        let (next_x, next_y) = match *direction {
+                                            Direction::Down => (entity.x, entity.y - 16),
+                                            Direction::Up => (entity.x, entity.y + 16),
+                                            Direction::Left => (entity.x - 16, entity.y),
+                                            Direction::Right => (entity.x + 16, entity.y),
+                                        };
+                                        if next_x < 0 || next_x > (level.width - 1) * 16 ||
+                                           (!level.wraparound && (next_y < 0 || next_y > (level.height - 1) * 16)) {
+                                            *direction = match *direction {
+                                                Direction::Down => Direction::Up,
+                                                Direction::Up => Direction::Down,
+                                                Direction::Left => Direction::Right,
+                                                Direction::Right => Direction::Left,
+                                            };
+                                        }
+                                    }
+                                    // Steps stop on tile boundaries so a platform at any speed lines up with arrows
+                                    let step = |position: i32, forwards: bool| {
+                                        let offset = (position % 16 + 16) % 16;
+                                        speed.min(if forwards { 16 - offset } else if offset == 0 { 16 } else { offset })
+                                    };
+                                    match *direction {
+                                        Direction::Down => entity.y -= step(entity.y, false),
+                                        Direction::Up => entity.y += step(entity.y, true),
+                                        Direction::Left => entity.x -= step(entity.x, false),
+                                        Direction::Right => entity.x += step(entity.x, true),
+                                    }
+                                }
+                                Route::Path(ref points, ref mut index) => {
+                                    let target = points[*index];
+                                    entity.x += (target[0] - entity.x).max(-speed).min(speed);
+                                    entity.y += (target[1] - entity.y).max(-speed).min(speed);
+                                    if entity.x == target[0] && entity.y == target[1] {
+                                        *index = (*index + 1) % points.len();
+                                    }
+                                }
                             }
+                            platforms.push(PlatformBox {
+                                x: entity.x,
+                                y: entity.y,
+                                dx: entity.x - old_x,
+                                dy: entity.y - old_y,
+                                solid: platform.solid,
+                            });
                         }
                     }
                     let mut player_x = 0;
                     let mut player_y = 0;
+                    let mut player_box = [0, 0, 0, 0];
                     let mut player_dead = false;
                     for entity in level.entities.iter_mut() {
                         if !entity.versions[level.version] {
                             continue;
                         }
-                        let grounded = Level::get_tile(&level.tile_map[level.version],
-                                                       entity.x,
-                                                       entity.y - 1,
-                                                       level.wraparound)
-                            .is_solid() ||
-                            Level::get_tile(&level.tile_map[level.version],
-                                            entity.x + 15,
-                                            entity.y - 1,
-                                            level.wraparound)
-                                .is_solid();
-                        let mut collisions = Vec::new();
-                        if entity.physics && !entity.dead {
-                            entity.x_speed *= 0.75;
-                            if entity.x_speed > 0.0 {
-                                if entity.x_speed < 1.0 {
-                                    entity.x_speed = 0.0;
-                                } else {
-                                    let target = entity.x + entity.x_speed as i32 + 16;
-                                    if target >= level.width as i32 * 16 ||
-                                        Level::get_tile(&level.tile_map[level.version],
-                                                        target,
-                                                        entity.y,
-                                                        level.wraparound)
-                                            .is_solid() ||
-                                        Level::get_tile(&level.tile_map[level.version],
-                                                        target,
-                                                        entity.y + 15,
-                                                        level.wraparound)
-                                            .is_solid() {
-                                        entity.x = (entity.x + entity.x_speed as i32) / 16 * 16;
-                                        entity.x_speed = 0.0;
-                                        collisions.push(Direction::Right);
-                                    } else {
-                                        entity.x += entity.x_speed as i32;
+                        let riding = match entity.entity_type {
+                            EntityType::Platform(_) => false,
+                            _ => entity.physics && !entity.dead,
+                        };
+                        if riding {
+                            for platform in &platforms {
+                                if entity.y == platform.y - platform.dy + 16 && entity.x + 15 >= platform.x - platform.dx && entity.x <= platform.x - platform.dx + 15 {
+                                    entity.y += platform.dy;
+                                    let target = if platform.dx > 0 { entity.x + platform.dx + 15 } else { entity.x + platform.dx };
+                                    if !Level::is_solid_at(&level.tile_map[level.version], target, entity.y, level.wraparound, false) &&
+                                        !Level::is_solid_at(&level.tile_map[level.version], target, entity.y + 15, level.wraparound, false) {
+                                        entity.x += platform.dx;
                                     }
+                                    break;
                                 }
-                            } else if entity.x_speed < 0.0 {
-                                if entity.x_speed > -1.0 {
-                                    entity.x_speed = 0.0
-                                } else {
-                                    let target = entity.x + entity.x_speed as i32;
-                                    if target < 0 ||
-                                        Level::get_tile(&level.tile_map[level.version],
-                                                        target,
-                                                        entity.y,
-                                                        level.wraparound)
-                                            .is_solid() ||
-                                        Level::get_tile(&level.tile_map[level.version],
-                                                        target,
-                                                        entity.y + 15,
-                                                        level.wraparound)
-                                            .is_solid() {
-                                        entity.x = (entity.x + entity.x_speed as i32 + 16) / 16 * 16;
-                                        entity.x_speed = 0.0;
-                                        collisions.push(Direction::Left);
-                                    } else {
-                                        entity.x += entity.x_speed as i32;
-                                    }
-                                }
-                            }
-                            if !grounded {
-                                entity.y_speed -= 0.18;
                             }
-                            if entity.y_speed > 0.0 {
-                                let target = entity.y + entity.y_speed as i32 + 16;
-                                if (!level.wraparound && target >= level.height as i32 * 16) ||
-                                    Level::get_tile(&level.tile_map[level.version],
-                                                    entity.x,
-                                                    target,
-                                                    level.wraparound)
-                                        .is_solid() ||
-                                    Level::get_tile(&level.tile_map[level.version],
-                                                    entity.x + 15,
-                                                    target,
-                                                    level.wraparound)
-                                        .is_solid() {
-                                    entity.y = (entity.y + entity.y_speed as i32) / 16 * 16;
-                                    entity.y_speed = 0.0;
-                                    collisions.push(Direction::Up);
-                                } else {
-                                    entity.y += entity.y_speed as i32;
+                        }
+                        let (profile, floating, climbing) = match entity.entity_type {
+                            EntityType::Player(ref player) => (player.movement[level.version], input.a.held && entity.y_speed < 0.0, player.climbing),
+                            _ => (MovementProfile::default(), false, false),
+                        };
+                        let (grounded, collisions, landed) = {
+                            let tile_map = &level.tile_map[level.version];
+                            let wraparound = level.wraparound;
+                            let phasing = profile.abilities.phase;
+                            let solid = |x: i32, y: i32| Level::is_solid_at(tile_map, x, y, wraparound, phasing);
+                            let standing = |x: i32, y: i32| {
+                                solid(x, y - 1) || solid(x + 15, y - 1) ||
+                                (!climbing && (Level::is_platform(tile_map, x, y - 1, y, wraparound) || Level::is_platform(tile_map, x + 15, y - 1, y, wraparound))) ||
+                                (riding && platforms.iter().any(|platform| y == platform.y + 16 && platform.overlaps_x(x)))
+                            };
+                            let grounded = standing(entity.x, entity.y);
+                            let mut collisions = Vec::new();
+                            let mut landed = false;
+                            if entity.physics && !entity.dead {
+                                entity.x_speed *= profile.friction;
+                                if entity.x_speed > -1.0 && entity.x_speed < 1.0 {
+                                    entity.x_speed = 0.0;
                                 }
-                            } else if entity.y_speed < 0.0 {
-                                if grounded {
-                                    entity.y_speed = 0.0;
+                                // Move a pixel at a time so slopes can be walked up by stepping over their lower edge
+                                let (direction, step) = if entity.x_speed > 0.0 { (Direction::Right, 1) } else { (Direction::Left, -1) };
+                                let mut remaining = (entity.x_speed as i32).abs();
+                                while remaining > 0 {
+                                    let front = if step > 0 { entity.x + 16 } else { entity.x - 1 };
+                                    let mut lift = 0;
+                                    while lift <= 4 && (solid(front, entity.y + lift) || solid(front, entity.y + lift + 15)) {
+                                        lift += 1;
+                                    }
+                                    let y = entity.y;
+                                    if front < 0 || front >= level.width as i32 * 16 || lift > 4 ||
+                                        platforms.iter().any(|platform| riding && platform.solid && platform.overlaps_y(y) && front >= platform.x && front <= platform.x + 15) {
+                                        entity.x_speed = 0.0;
+                                        collisions.push(direction);
+                                        break;
+                                    }
+                                    entity.x += step;
+                                    entity.y += lift;
+                                    remaining -= 1;
                                 }
-                                if entity.y_speed < -2.0 {
-                                    entity.y_speed = -2.0
+                                if grounded && !climbing && entity.y_speed <= 0.0 && !standing(entity.x, entity.y) {
+                                    for drop in 1..5 {
+                                        if standing(entity.x, entity.y - drop) {
+                                            entity.y -= drop;
+                                            break;
+                                        }
+                                    }
                                 }
-                                let target = entity.y + entity.y_speed as i32;
-                                if (!level.wraparound && target < 0) ||
-                                    Level::get_tile(&level.tile_map[level.version],
-                                                    entity.x,
-                                                    target,
-                                                    level.wraparound)
-                                        .is_solid() ||
-                                    Level::get_tile(&level.tile_map[level.version],
-                                                    entity.x + 15,
-                                                    target,
-                                                    level.wraparound)
-                                        .is_solid() {
-                                    entity.y = (entity.y + entity.y_speed as i32 + 16) / 16 * 16;
-                                    entity.y_speed = 0.0;
-                                } else {
-                                    entity.y += entity.y_speed as i32;
+                                let float = if floating { profile.abilities.float } else { 1.0 };
+                                if !grounded && !climbing {
+                                    entity.y_speed -= profile.gravity * float;
                                 }
+                                if entity.y_speed > 0.0 {
+                                    let mut remaining = entity.y_speed as i32;
+                                    while remaining > 0 {
+                                        let (x, top) = (entity.x, entity.y + 16);
+                                        if (!wraparound && top >= level.height as i32 * 16) || solid(x, top) || solid(x + 15, top) ||
+                                            platforms.iter().any(|platform| riding && platform.solid && platform.overlaps_x(x) && top == platform.y) {
+                                            entity.y_speed = 0.0;
+                                            collisions.push(Direction::Up);
+                                            break;
+                                        }
+                                        entity.y += 1;
+                                        remaining -= 1;
+                                    }
+                                } else if entity.y_speed < 0.0 {
+                                    if grounded {
+                                        entity.y_speed = 0.0;
+                                    }
+                                    if entity.y_speed < -profile.max_fall * float {
+                                        entity.y_speed = -profile.max_fall * float
+                                    }
+                                    let mut remaining = -entity.y_speed as i32;
+                                    while remaining > 0 {
+                                        if (!wraparound && entity.y - 1 < 0) || standing(entity.x, entity.y) {
+                                            entity.y_speed = 0.0;
+                                            break;
+                                        }
+                                        entity.y -= 1;
+                                        remaining -= 1;
+                                    }
+                                }
+                                // Checked after moving since a fall can end exactly as the pixels run out
+                                landed = !grounded && entity.y_speed <= 0.0 && standing(entity.x, entity.y);
                             }
-                        }
+                            (grounded, collisions, landed)
+                        };
                         if level.wraparound {
                             entity.y = (entity.y + 16 * level.height as i32) % (16 * level.height as i32)
                         }
+                        if landed {
+                            events.push((GameEvent::Land, [entity.x + 8, entity.y]));
+                        }
                         if let Some(sprite) = match entity.entity_type {
                             EntityType::Player(ref mut player) => {
                                 player_x = entity.x;
                                 player_y = entity.y;
+                                let sprite_events = player.sprites[level.version].get(&player.state).update();
+                                for event in &sprite_events {
+                                    if let SpriteEvent::Marker(ref name) = *event {
+                                        if let Some(event) = GameEvent::parse(name) {
+                                            events.push((event, [entity.x + 8, entity.y]));
+                                        }
+                                    }
+                                }
+                                let finished = sprite_events.contains(&SpriteEvent::Finished);
                                 match player.state {
                                     PlayerState::Dying => {
-                                        if let Some(ref mut animator) = player.sprites[level.version].dying.animator {
-                                            if animator.instant.elapsed() > player.sprites[level.version].dying.texture[animator.index].1 {
-                                                animator.instant = Instant::now();
-                                                animator.index += 1;
-                                            }
-                                            if animator.index >= player.sprites[level.version].dying.texture.len() {
-                                                animator.index = 0;
-                                                level.switch = 60;
-                                                entity.dead = false;
-                                                level.version = level.version ^ 1;
-                                            }
+                                        if finished {
+                                            level.transition = Some((Trigger::Dying, 0));
+                                            events.push((GameEvent::Switch, [entity.x + 8, entity.y + 8]));
+                                            entity.dead = false;
+                                            level.version = level.version ^ 1;
                                         }
                                     },
                                     PlayerState::Turning(ref level_name) => {
-                                        if let Some(ref mut animator) = player.sprites[level.version].turning.animator {
-                                            if animator.instant.elapsed() > player.sprites[level.version].turning.texture[animator.index].1 {
-                                                animator.instant = Instant::now();
-                                                animator.index += 1;
-                                            }
-                                            if animator.index >= player.sprites[level.version].turning.texture.len() {
-                                                new_level = Some(level_name.clone());
-                                                break;
-                                            }
+                                        if finished {
+                                            new_level = Some(level_name.clone());
+                                            break;
                                         }
                                     },
                                     PlayerState::Reviving => {
-                                        if let Some(ref mut animator) = player.sprites[level.version].reviving.animator {
-                                            if animator.instant.elapsed() > player.sprites[level.version].reviving.texture[animator.index].1 {
-                                                animator.instant = Instant::now();
-                                                animator.index += 1;
-                                            }
-                                            if animator.index >= player.sprites[level.version].reviving.texture.len() {
-                                                level.switch = 60;
-                                                level.version = level.version ^ 1;
-                                            }
+                                        if finished {
+                                            level.transition = Some((Trigger::Reviving, 0));
+                                            events.push((GameEvent::Switch, [entity.x + 8, entity.y + 8]));
+                                            level.version = level.version ^ 1;
                                         }
                                     },
                                     _ => {
-                                        entity.dead = entity.dead || (!level.wraparound && entity.y <= 0);
+                                        entity.dead = entity.dead || (!level.wraparound && entity.y <= 0) ||
+                                                      Level::is_hazard(&level.tile_map[level.version], entity.x, entity.y, level.wraparound);
                                         if entity.dead {
+                                            level.camera.shake(20, 2);
+                                            events.push((GameEvent::Hurt, [entity.x + 8, entity.y + 8]));
                                             player.state = PlayerState::Dying;
                                             player.sprites[level.version].dying.reset();
                                         } else {
+                                            let was_walking = player.state == PlayerState::Walking;
                                             player.state = PlayerState::Standing;
-                                            if input.left {
+                                            let ladder = Level::get_tile(&level.tile_map[level.version], entity.x + 8, entity.y + 8, level.wraparound).is_ladder();
+                                            let ladder_below = Level::get_tile(&level.tile_map[level.version], entity.x + 8, entity.y - 1, level.wraparound).is_ladder();
+                                            if (input.up.held && ladder) || (input.down.held && ladder_below) {
+                                                player.climbing = true;
+                                            }
+                                            if !ladder && !ladder_below {
+                                                player.climbing = false;
+                                            }
+                                            if input.left.held {
                                                 player.state = PlayerState::Walking;
                                                 entity.facing = false;
-                                                entity.x_speed = -2.0;
-                                            } else if input.right {
+                                                entity.x_speed = (entity.x_speed - profile.acceleration).max(-profile.walk_speed);
+                                            } else if input.right.held {
                                                 player.state = PlayerState::Walking;
                                                 entity.facing = true;
-                                                entity.x_speed = 2.0;
+                                                entity.x_speed = (entity.x_speed + profile.acceleration).min(profile.walk_speed);
                                             }
-                                            if input.a && grounded {
+                                            // Walks start from the first frame so its footstep lands with the first step
+                                            if player.state == PlayerState::Walking && !was_walking {
+                                                player.sprites[level.version].walking.reset();
+                                            }
+                                            // Grounded is from before this frame's move, so it's still set on the way up from a jump
+                                            if grounded && entity.y_speed <= 0.0 {
+                                                player.coyote = profile.coyote_frames;
+                                                player.air_jumps = profile.abilities.air_jumps;
+                                            }
+                                            if input.a.pressed {
+                                                player.jump_buffer = profile.jump_buffer + 1;
+                                            }
+                                            if input.down.held && input.a.pressed && grounded && !player.climbing &&
+                                                !Level::get_tile(&level.tile_map[level.version], entity.x, entity.y - 1, level.wraparound).is_solid() &&
+                                                !Level::get_tile(&level.tile_map[level.version], entity.x + 15, entity.y - 1, level.wraparound).is_solid() {
+                                                entity.y -= 1;
+                                                player.jump_buffer = 0;
+                                            } else if player.jump_buffer > 0 && (grounded || player.coyote > 0 || player.climbing) {
+                                                player.climbing = false;
                                                 player.state = PlayerState::Jumping;
-                                                entity.y_speed = 4.0;
+                                                events.push((GameEvent::Jump, [entity.x + 8, entity.y]));
+                                                entity.y_speed = profile.jump_speed;
+                                                player.jump_buffer = 0;
+                                                player.coyote = 0;
+                                                player.jump_held = true;
+                                            } else if input.a.pressed && player.air_jumps > 0 {
+                                                player.state = PlayerState::Jumping;
+                                                events.push((GameEvent::Jump, [entity.x + 8, entity.y]));
+                                                entity.y_speed = profile.jump_speed;
+                                                player.jump_buffer = 0;
+                                                player.air_jumps -= 1;
+                                                player.jump_held = true;
+                                            }
+                                            if player.jump_buffer > 0 {
+                                                player.jump_buffer -= 1;
                                             }
-                                            if entity.y_speed < 0.0 {
+                                            if !grounded && player.coyote > 0 {
+                                                player.coyote -= 1;
+                                            }
+                                            if profile.abilities.push {
+                                                for collision in &collisions {
+                                                    let front_x = match *collision {
+                                                        Direction::Left => entity.x - 1,
+                                                        Direction::Right => entity.x + 16,
+                                                        _ => continue,
+                                                    };
+                                                    Level::push_tile(&mut level.tile_map[level.version], front_x, entity.y + 8, *collision, level.wraparound);
+                                                }
+                                            }
+                                            if player.jump_held && !input.a.held {
+                                                if entity.y_speed > 0.0 {
+                                                    entity.y_speed *= profile.jump_release;
+                                                }
+                                                player.jump_held = false;
+                                            }
+                                            if player.climbing {
+                                                player.state = PlayerState::Climbing;
+                                                entity.y_speed = if input.up.held {
+                                                    profile.climb_speed
+                                                } else if input.down.held {
+                                                    -profile.climb_speed
+                                                } else {
+                                                    0.0
+                                                };
+                                            } else if entity.y_speed < 0.0 {
                                                 player.state = PlayerState::Falling;
                                                 player.sprites[level.version].falling.reset();
                                             }
-                                            if input.b {
+                                            if input.b.pressed {
                                                 match Level::get_tile(&level.tile_map[level.version],
                                                                       entity.x + 8,
                                                                       entity.y + 8,
@@ -1000,45 +1824,24 @@ impl Game {
                                         }
                                     }
                                 }
-                                Option::Some(match player.state {
-                                    PlayerState::Falling => player.sprites[level.version].falling.texture(),
-                                    PlayerState::Standing => player.sprites[level.version].standing.texture(),
-                                    PlayerState::Jumping => player.sprites[level.version].jumping.texture(),
-                                    PlayerState::Walking => player.sprites[level.version].walking.texture(),
-                                    PlayerState::Dying => {
-                                        let mut index = 0;
-                                        if let Some(ref mut animator) = player.sprites[level.version].dying.animator {
-                                            index = animator.index;
-                                        }
-                                        player.sprites[level.version].dying.texture[index].0.clone()
-                                    },
-                                    PlayerState::Reviving => {
-                                        let mut index = 0;
-                                        if let Some(ref mut animator) = player.sprites[level.version].reviving.animator {
-                                            index = animator.index;
-                                        }
-                                        player.sprites[level.version].reviving.texture[index].0.clone()
-                                    },
-                                    PlayerState::Turning(_) => {
-                                        let mut index = 0;
-                                        if let Some(ref mut animator) = player.sprites[level.version].turning.animator {
-                                            index = animator.index;
-                                        }
-                                        player.sprites[level.version].turning.texture[index].0.clone()
-                                    },
-                                })
+                                let sprite = player.sprites[level.version].get(&player.state);
+                                player_box = sprite.hitbox([entity.x, entity.y], entity.facing);
+                                Some((sprite.frame(), sprite.origin(entity.facing)))
                             }
                             EntityType::Enemy(ref mut enemy) => {
-                                if enemy.deadly {
-                                    let x_distance = player_x - entity.x;
-                                    let y_distance = player_y - entity.y;
-                                    if x_distance * x_distance + y_distance * y_distance < 32 * 32 {
-                                        if player_x + 15 >= entity.x && player_x <= entity.x + 15 && player_y + 15 >= entity.y && player_y <= entity.y + 15 {
-                                            player_dead = true;
-                                        }
-                                    }
+                                // Walking into spikes kills anything that collides with tiles, and it stays gone until the level restarts
+                                if enemy.collision && !entity.dead && Level::is_hazard(&level.tile_map[level.version], entity.x, entity.y, level.wraparound) {
+                                    entity.dead = true;
+                                    events.push((GameEvent::EnemyDeath, [entity.x + 8, entity.y + 8]));
+                                }
+                                if entity.dead {
+                                    continue;
+                                }
+                                if enemy.deadly && overlaps(player_box, enemy.sprites[level.version].hitbox([entity.x, entity.y], entity.facing)) {
+                                    player_dead = true;
                                 }
                                 let AI::Pace(ref mut direction) = enemy.ai;
+                                let old_direction = *direction;
                                 {
                                     if *direction == Direction::Left {
                                         entity.facing = false;
@@ -1071,11 +1874,11 @@ impl Game {
                                         }
                                     }
                                     if enemy.gravity {
-                                        if !Level::get_tile(&level.tile_map[1],
-                                                            entity.x + entity.x_speed as i32 + 8,
-                                                            entity.y - 8,
-                                                            level.wraparound)
-                                            .is_solid() {
+                                        // Probe far enough down that walking off the top of a slope doesn't count as an edge
+                                        let (ahead_x, below_y, feet) = (entity.x + entity.x_speed as i32 + 8, entity.y - 12, entity.y);
+                                        if !Level::is_solid_at(&level.tile_map[level.version], ahead_x, below_y, level.wraparound, false) &&
+                                            !Level::is_platform(&level.tile_map[level.version], ahead_x, below_y, feet, level.wraparound) &&
+                                            !platforms.iter().any(|platform| platform.contains(ahead_x, below_y)) {
                                             if entity.x_speed < -0.5 {
                                                 *direction = Direction::Right;
                                             } else if entity.x_speed > 0.5 {
@@ -1092,49 +1895,168 @@ impl Game {
                                         }
                                     }
                                 }
-                                Some(enemy.sprites[level.version].texture())
+                                let sprite = &mut enemy.sprites[level.version];
+                                if *direction != old_direction {
+                                    sprite.play("turn");
+                                }
+                                for event in sprite.update() {
+                                    match event {
+                                        // Turns and other one-off animations go back to walking
+                                        SpriteEvent::Finished => {
+                                            if sprite.finished() {
+                                                sprite.play("default");
+                                            }
+                                        }
+                                        SpriteEvent::Marker(name) => {
+                                            if let Some(event) = GameEvent::parse(&name) {
+                                                events.push((event, [entity.x + 8, entity.y]));
+                                            }
+                                        }
+                                    }
+                                }
+                                Some((sprite.frame(), sprite.origin(entity.facing)))
                             },
+                            EntityType::Platform(ref mut platform) => Some((platform.sprite.texture(), platform.sprite.origin(entity.facing))),
                             EntityType::Key(ref mut key) => {
                                 let x_distance = player_x - entity.x;
                                 let y_distance = player_y - entity.y;
-                                if !key.collected {
-                                    if x_distance * x_distance + y_distance * y_distance < 32 * 32 {
-                                        if player_x + 15 >= entity.x && player_x <= entity.x + 15 && player_y + 15 >= entity.y && player_y <= entity.y + 15 {
-                                            key.collected = true;
-                                            level.keys_collected += 1;
-                                            key.distance = level.keys_collected as i32 * 12;
-                                            entity.versions = [true, true];
-                                        }
-                                    }
+                                if !key.collected && overlaps(player_box, key.sprite.hitbox([entity.x, entity.y], entity.facing)) {
+                                    key.collected = true;
+                                    events.push((GameEvent::KeyGet, [entity.x + 8, entity.y + 8]));
+                                    level.keys_collected += 1;
+                                    key.distance = level.keys_collected as i32 * 12;
+                                    entity.versions = [true, true];
                                 }
                                 if key.collected {
                                         entity.x += x_distance / key.distance;
                                         entity.y += y_distance / (key.distance / 2);
                                 }
-                                Some(key.sprite.texture())
+                                Some((key.sprite.texture(), key.sprite.origin(entity.facing)))
                             }
-                        } {
-                            relative_sprites.push((sprite.clone(), [entity.x, entity.y], entity.facing));
-                            if level.wraparound && entity.y > (level.height as i32 * 16 - 16) {
-                                relative_sprites.push((sprite, [entity.x, entity.y - (level.height as i32 * 16)], entity.facing));
+                            EntityType::Sign(ref mut sign) => {
+                                if input.b.pressed && level.dialogue.is_none() && (player_x - entity.x).abs() < 16 && (player_y - entity.y).abs() < 16 {
+                                    level.dialogue = Some(Dialogue::new(self.strings.text(&sign.message)));
+                                    level.paused = true;
+                                }
+                                Some((sign.sprite.texture(), sign.sprite.origin(entity.facing)))
                             }
+                        } {
+                            let (sprite, origin) = sprite;
+                            relative_sprites.push((Layer::Entities, sprite, [entity.x - origin[0], entity.y - origin[1]], entity.facing));
                         }
                     }
                     if player_dead {
                         level.entities[0].dead = true;
                     }
                 }
+                for &(event, position) in &events {
+                    level.particles.spawn(&self.config.particles, event, position, level.version);
+                }
+                // Particles keep going through transitions so wisps can drift while the level is frozen
+                if !level.paused || level.transition.is_some() {
+                    level.particles.step();
+                }
                 if !level.paused {
                     level.pause_sprites[level.version] = relative_sprites;
                 }
-                if level.switch == 60 {
+                if let Some((_, 0)) = level.transition {
                     level.version = level.version ^ 1;
                 }
-                for &(ref sprite, position, flip) in &level.pause_sprites[level.version & 1]{
-                    sprites.push((sprite.clone(), [position[0] - camera_x, position[1] - camera_y], flip));
+                if !level.paused {
+                    let (x, y, facing) = {
+                        let player = &level.entities[0];
+                        (player.x, player.y, player.facing)
+                    };
+                    level.camera.update([x, y], facing, [level.width * 16, level.height * 16], level.wraparound);
+                }
+                let view = level.camera.view();
+                let (camera_x, camera_y) = (view[0], view[1]);
+                if !level.paused {
+                    let mut tiles = Vec::new();
+                    let textures: Vec<Image> = level.tile_sprites.iter_mut().map(|sprite| sprite.texture()).collect();
+                    // Only the tiles in this frame's view, with a tile to spare on each side
+                    let columns = (std::cmp::max(0, view[0] / 16 - 1), std::cmp::min(level.width, view[0] / 16 + 12));
+                    let mut rows = (view[1] / 16 - 1, view[1] / 16 + 11);
+                    if !level.wraparound {
+                        rows = (std::cmp::max(0, rows.0), std::cmp::min(level.height, rows.1));
+                    } else if rows.1 - rows.0 > level.height {
+                        rows = (0, level.height);
+                    }
+                    for row in rows.0..rows.1 {
+                        let y = ((row % level.height + level.height) % level.height) as usize;
+                        for x in columns.0 as usize..columns.1 as usize {
+                            let tile = &level.tile_map[level.version][y][x];
+                            let mut sprite_id = tile.sprite_id;
+                            if let TileType::Door(_, sprites) = tile.tile_type {
+                                sprite_id = sprites[(level.keys_collected >= level.key_count) as usize];
+                            }
+                            if let TileType::KeyBackground(sprites) = tile.tile_type {
+                                sprite_id = sprites[(level.keys_collected >= level.key_count) as usize];
+                            }
+                            if tile.sprite_id != 0 {
+                                tiles.push((tile.layer, textures[sprite_id].clone(), [x as i32 * 16, y as i32 * 16], false))
+                            }
+                        }
+                    }
+                    level.pause_sprites[level.version].append(&mut tiles);
+                }
+                for background in level.backgrounds.iter_mut() {
+                    if !background.versions[level.version & 1] {
+                        continue;
+                    }
+                    if !level.paused {
+                        background.offset[0] += background.autoscroll[0];
+                        background.offset[1] += background.autoscroll[1];
+                    }
+                    let texture = background.sprite.texture();
+                    let size = [texture.width as i32, texture.height as i32];
+                    let mut start = [0; 2];
+                    let mut end = [0; 2];
+                    for axis in 0..2 {
+                        let camera = if axis == 0 { camera_x } else { camera_y };
+                        let position = background.offset[axis] as i32 - (camera as f32 * background.scroll[axis]) as i32;
+                        if background.repeat[axis] {
+                            start[axis] = (position % size[axis] + size[axis]) % size[axis] - size[axis];
+                            end[axis] = if axis == 0 { 160 } else { 144 };
+                        } else {
+                            start[axis] = position;
+                            end[axis] = position + 1;
+                        }
+                    }
+                    let mut y = start[1];
+                    while y < end[1] {
+                        let mut x = start[0];
+                        while x < end[0] {
+                            sprites.push(Layer::FarBackground, (texture.clone(), [x, y], false));
+                            x += size[0];
+                        }
+                        y += size[1];
+                    }
+                }
+                // Wrapping levels are drawn again above and below so the seam never shows
+                let copies = if level.wraparound { vec![-level.height * 16, 0, level.height * 16] } else { vec![0] };
+                for &(layer, ref sprite, position, flip) in &level.pause_sprites[level.version & 1] {
+                    for copy in &copies {
+                        let y = position[1] + copy - camera_y;
+                        if y > -(sprite.height as i32) && y < 144 {
+                            sprites.push(layer, (sprite.clone(), [position[0] - camera_x, y], flip));
+                        }
+                    }
+                }
+                sprites.append(Layer::Particles, &mut level.particles.sprites(view, level.version & 1));
+                if let Some(fade) = fade_index.and_then(|index| level.fade.get(index)) {
+                    sprites.push(Layer::Fade, (fade.clone(), [0, 0], false));
+                }
+                if !level.paused {
+                    level.frames += 1;
+                }
+                // Unpaused only now so the A press that closed the box doesn't also jump
+                if dialogue_closed {
+                    level.paused = false;
                 }
-                if self.palette_id == 4 {
-                    sprites.push((level.fade[fade_index as usize].clone(), [0, 0], false));
+                hud = Some((level.name.clone(), level.frames, [level.keys_collected, level.key_count], level.version & 1));
+                if let Some(ref dialogue) = level.dialogue {
+                    text_box = Some((dialogue.pages[dialogue.page].clone(), dialogue.shown, dialogue.page + 1 < dialogue.pages.len()));
                 }
                 None
             }
@@ -1143,10 +2065,287 @@ impl Game {
         }
         if let Some(level_name) = new_level {
             //self.music.send(0.0).unwrap();
-            self.state = GameState::Level(Game::load_level(&self.textures, &self.levels.get(&level_name).unwrap()));
+            self.start_level(&level_name);
+        }
+        // Unpaused only now so the A or B press that left the menu doesn't also reach the level
+        if menu_open && self.menus.is_empty() {
+            if let GameState::Level(ref mut level) = self.state {
+                level.paused = level.transition.is_some() || level.dialogue.is_some();
+            }
+        }
+        if open_pause {
+            if let GameState::Level(ref mut level) = self.state {
+                level.paused = true;
+            }
+            self.menus.push(Menu::new(MenuKind::Pause));
+        }
+        if let Some((name, frames, keys, version)) = hud {
+            if self.settings.show_hud {
+                let mut hud = self.hud(&name, frames, keys, version);
+                sprites.append(Layer::Hud, &mut hud);
+            }
+        }
+        if let Some((page, shown, more)) = text_box {
+            sprites.push(Layer::Overlay, (self.text_box.clone(), [2, 12], false));
+            let mut text = self.typed_text(&page, 8, 44, 144, shown);
+            sprites.append(Layer::Overlay, &mut text);
+            if more && shown >= page.chars().count() {
+                sprites.push(Layer::Overlay, (self.menu_icon.texture(), [148, 14], false));
+            }
+        }
+        let mut menu = self.draw_menu();
+        sprites.append(Layer::Overlay, &mut menu);
+        sprites.into_sprites()
+    }
+
+    fn start_level(&mut self, id: &str) {
+        self.state = GameState::Level(Game::load_level(&self.textures, &self.config, id, &self.levels.get(id).unwrap()));
+        self.menus.clear();
+    }
+
+    fn menu_entries(&self, kind: MenuKind) -> Vec<String> {
+        let keys = match kind {
+            MenuKind::Title => vec!["menu.start", "menu.level_select", "menu.options", "menu.quit"],
+            MenuKind::Pause => {
+                vec!["menu.resume", "menu.restart_checkpoint", "menu.restart_level", "menu.level_select", "menu.options", "menu.quit_to_title"]
+            }
+            MenuKind::LevelSelect => {
+                let mut entries: Vec<String> = self.level_order.iter().map(|id| self.level_title(id)).collect();
+                entries.push(self.strings.get("menu.back").to_string());
+                return entries;
+            }
+            MenuKind::Options => {
+                return vec![self.strings.get(if self.settings.show_hud { "menu.hud_on" } else { "menu.hud_off" }).to_string(),
+                            format!("{} {}", self.strings.get("menu.language"), self.strings.get("lang.name")),
+                            format!("{} {}X", self.strings.get("menu.scale"), self.settings.scale),
+                            self.strings.get(if self.settings.fullscreen { "menu.fullscreen_on" } else { "menu.fullscreen_off" }).to_string(),
+                            format!("{} {}",
+                                    self.strings.get("menu.palette"),
+                                    self.palette_pairs
+                                        .iter()
+                                        .find(|pair| pair.id == self.settings.palette)
+                                        .map_or("", |pair| self.strings.text(&pair.name))),
+                            format!("{} {}", self.strings.get("menu.volume"), self.settings.volume),
+                            format!("{} {}",
+                                    self.strings.get("menu.gamepad"),
+                                    match self.settings.gamepad.as_ref() {
+                                        "any" => self.strings.get("menu.gamepad_any"),
+                                        "off" => self.strings.get("menu.gamepad_off"),
+                                        name => name,
+                                    }),
+                            self.strings.get("menu.effects").to_string(),
+                            self.strings.get("menu.controls").to_string(),
+                            self.strings.get("menu.back").to_string()];
+            }
+            MenuKind::Effects => {
+                let on_off = |on: bool| self.strings.get(if on { "menu.on" } else { "menu.off" });
+                return vec![format!("{} {}", self.strings.get("menu.lcd_grid"), on_off(self.settings.lcd_grid)),
+                            format!("{} {}", self.strings.get("menu.ghosting"), on_off(self.settings.ghosting)),
+                            format!("{} {}", self.strings.get("menu.scanlines"), on_off(self.settings.scanlines)),
+                            format!("{} {}", self.strings.get("menu.dither"), on_off(self.settings.dither)),
+                            self.strings.get("menu.back").to_string()];
+            }
+            MenuKind::Palettes => {
+                let mut entries: Vec<String> = self.palette_pairs.iter().map(|pair| self.strings.text(&pair.name).to_string()).collect();
+                entries.push(self.strings.get("menu.back").to_string());
+                return entries;
+            }
+            MenuKind::Controls => {
+                let mut entries: Vec<String> = ACTIONS.iter()
+                    .map(|&action| {
+                        let keys = if self.rebinding == Some(action) {
+                            self.strings.get("controls.press_key").to_string()
+                        } else {
+                            self.settings.keys(action).join(" ")
+                        };
+                        format!("{} {}", self.strings.get(&format!("controls.{}", action.name())), keys)
+                    })
+                    .collect();
+                entries.push(self.strings.get("menu.reset_controls").to_string());
+                entries.push(self.strings.get("menu.back").to_string());
+                return entries;
+            }
+        };
+        keys.iter().map(|key| self.strings.get(key).to_string()).collect()
+    }
+
+    /// The title from a level's TITLE section without loading the whole level
+    fn level_title(&self, id: &str) -> String {
+        let mut lines = self.levels.get(id).unwrap().lines().skip_while(|line| line.trim() != "TITLE");
+        match lines.nth(1) {
+            Some(title) => self.strings.text(title.trim()).to_string(),
+            None => id.replace('_', " "),
+        }
+    }
+
+    /// Moves through the top menu, B backs out of anything but the title
+    fn menu_step(&mut self, input: &Input) {
+        if self.rebinding.is_some() {
+            return;
+        }
+        let kind = self.menus.last().unwrap().kind;
+        let entries = self.menu_entries(kind).len();
+        if input.b.pressed && kind != MenuKind::Title {
+            self.close_menu();
+        } else if let Some(selection) = self.menus.last_mut().unwrap().step(input, entries) {
+            self.menu_select(kind, selection);
+        }
+    }
+
+    fn close_menu(&mut self) {
+        self.menus.pop();
+    }
+
+    fn menu_select(&mut self, kind: MenuKind, selection: usize) {
+        match (kind, selection) {
+            (MenuKind::Title, 0) => {
+                let id = self.level_order.first().cloned().unwrap_or_else(|| "Tutorial_Level".to_string());
+                self.start_level(&id);
+            }
+            (MenuKind::Title, 1) | (MenuKind::Pause, 3) => self.menus.push(Menu::new(MenuKind::LevelSelect)),
+            (MenuKind::Title, 2) | (MenuKind::Pause, 4) => self.menus.push(Menu::new(MenuKind::Options)),
+            (MenuKind::Title, _) => self.quit = true,
+            (MenuKind::Pause, 0) => self.close_menu(),
+            (MenuKind::Pause, 1) => {
+                if let GameState::Level(ref mut level) = self.state {
+                    level.restart_from_checkpoint();
+                }
+                self.menus.clear();
+            }
+            (MenuKind::Pause, 2) => {
+                let id = match self.state {
+                    GameState::Level(ref level) => level.id.clone(),
+                    GameState::Title => return,
+                };
+                self.start_level(&id);
+            }
+            (MenuKind::Pause, _) => {
+                self.state = GameState::Title;
+                self.menus = vec![Menu::new(MenuKind::Title)];
+            }
+            (MenuKind::LevelSelect, _) => {
+                if selection < self.level_order.len() {
+                    let id = self.level_order[selection].clone();
+                    self.start_level(&id);
+                } else {
+                    self.close_menu();
+                }
+            }
+            (MenuKind::Options, 0) => {
+                self.settings.show_hud = !self.settings.show_hud;
+                self.settings.save();
+            }
+            (MenuKind::Options, 1) => {
+                self.strings.next_language();
+                self.settings.language = self.strings.code().to_string();
+                self.settings.save();
+            }
+            (MenuKind::Options, 2) => {
+                self.settings.scale = self.settings.scale % 6 + 1;
+                self.settings.save();
+            }
+            (MenuKind::Options, 3) => {
+                self.settings.fullscreen = !self.settings.fullscreen;
+                self.settings.save();
+            }
+            (MenuKind::Options, 4) => {
+                let mut menu = Menu::new(MenuKind::Palettes);
+                menu.selection = self.palette_pairs.iter().position(|pair| pair.id == self.settings.palette).unwrap_or(0);
+                self.menus.push(menu);
+            }
+            (MenuKind::Options, 5) => {
+                self.settings.volume = (self.settings.volume + 1) % 11;
+                self.settings.save();
+            }
+            (MenuKind::Options, 6) => {
+                // Off, any pad, then each connected pad by name
+                let mut choices = vec!["off".to_string(), "any".to_string()];
+                choices.extend(self.gamepads.connected.iter().map(|pad| pad.1.clone()));
+                let current = choices.iter().position(|choice| *choice == self.settings.gamepad).unwrap_or(0);
+                self.settings.gamepad = choices[(current + 1) % choices.len()].clone();
+                self.settings.save();
+            }
+            (MenuKind::Options, 7) => self.menus.push(Menu::new(MenuKind::Effects)),
+            (MenuKind::Options, 8) => self.menus.push(Menu::new(MenuKind::Controls)),
+            (MenuKind::Options, _) => self.close_menu(),
+            (MenuKind::Effects, 0) => {
+                self.settings.lcd_grid = !self.settings.lcd_grid;
+                self.settings.save();
+            }
+            (MenuKind::Effects, 1) => {
+                self.settings.ghosting = !self.settings.ghosting;
+                self.settings.save();
+            }
+            (MenuKind::Effects, 2) => {
+                self.settings.scanlines = !self.settings.scanlines;
+                self.settings.save();
+            }
+            (MenuKind::Effects, 3) => {
+                self.settings.dither = !self.settings.dither;
+                self.settings.save();
+            }
+            (MenuKind::Effects, _) => self.close_menu(),
+            (MenuKind::Palettes, _) => {
+                if selection < self.palette_pairs.len() {
+                    self.palettes = self.palette_pairs[selection].colours;
+                    self.palette_changed = true;
+                    self.settings.palette = self.palette_pairs[selection].id.clone();
+                    self.settings.save();
+                } else {
+                    self.close_menu();
+                }
+            }
+            (MenuKind::Controls, _) => {
+                if selection < ACTIONS.len() {
+                    self.rebinding = Some(ACTIONS[selection]);
+                } else if selection == ACTIONS.len() {
+                    self.settings.reset_bindings();
+                    self.settings.save();
+                } else {
+                    self.close_menu();
+                }
+            }
+        }
+    }
+
+    /// Called with every key pressed, returns true if the key was used to rebind an action
+    pub fn key_pressed(&mut self, key: &str) -> bool {
+        match self.rebinding.take() {
+            Some(action) => {
+                self.settings.bind(action, key);
+                self.settings.save();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Draws the top menu, on a panel over the frozen level or straight onto the title screen
+    fn draw_menu(&mut self) -> Vec<(Image, [i32; 2], bool)> {
+        let mut sprites = Vec::new();
+        let (kind, selection) = match self.menus.last() {
+            Some(menu) => (menu.kind, menu.selection),
+            None => return sprites,
+        };
+        let entries = self.menu_entries(kind);
+        let (x, mut y) = if kind == MenuKind::Title {
+            (48, 40)
+        } else {
+            sprites.push((self.menu_panel.clone(), [2, 32], false));
+            (16, 98)
+        };
+        // Scroll long lists so the selection stays on the panel
+        let visible = if kind == MenuKind::Title { 4 } else { 7 };
+        let first = if selection >= visible { selection + 1 - visible } else { 0 };
+        for (i, entry) in entries.iter().enumerate().skip(first).take(visible) {
+            let entry = self.font.truncate(entry, 156 - x);
+            if i == selection {
+                sprites.push((self.menu_icon.texture(), [x - 10, y], false));
+            }
+            let mut text = self.text(&entry, x, y, 160 - x);
+            sprites.append(&mut text);
+            y -= 10;
         }
-        //        let mut text = self.text("Okay it works, cool", 20, 130, 116);
-        // sprites.append(&mut text);
         sprites
     }
 }
@@ -1162,11 +2361,170 @@ impl Level {
             Rc::new(Tile {
                 sprite_id: 0,
                 tile_type: TileType::Background,
+                shape: [16, 16],
+                layer: Layer::BackgroundTiles,
             })
         } else {
             tile_map[(y / 16) as usize][(x / 16) as usize].clone()
         }
     }
+
+    /// Brings the player back in the other version, pushed up out of any wall when dying and at the checkpoint when
+    /// reviving
+    pub fn respawn(&mut self) {
+        let from = self.version;
+        {
+            let player_entity = &mut self.entities[0];
+            player_entity.dead = false;
+            if let EntityType::Player(ref mut player) = player_entity.entity_type {
+                player.state = PlayerState::Standing;
+                if self.version == 0 {
+                    while Level::get_tile(&self.tile_map[1], player_entity.x, player_entity.y, self.wraparound).is_solid() ||
+                          Level::get_tile(&self.tile_map[1], player_entity.x + 15, player_entity.y, self.wraparound).is_solid() {
+                        player_entity.y += 16;
+                    }
+                    self.version = 1;
+                } else {
+                    player_entity.x = player.checkpoint_x;
+                    player_entity.y = player.checkpoint_y;
+                    self.version = 0;
+                }
+            }
+        }
+        self.sync_links(from);
+    }
+
+    /// Puts the player back at their checkpoint in the life version, as if they had just respawned
+    pub fn restart_from_checkpoint(&mut self) {
+        let from = self.version;
+        {
+            let player_entity = &mut self.entities[0];
+            player_entity.dead = false;
+            player_entity.x_speed = 0.0;
+            player_entity.y_speed = 0.0;
+            if let EntityType::Player(ref mut player) = player_entity.entity_type {
+                player.state = PlayerState::Standing;
+                player.climbing = false;
+                player_entity.x = player.checkpoint_x;
+                player_entity.y = player.checkpoint_y;
+            }
+        }
+        self.version = 0;
+        self.transition = None;
+        self.particles.clear();
+        self.paused = false;
+        if from != 0 {
+            self.sync_links(from);
+        }
+    }
+
+    /// Hands the position and state of linked creatures over to their other half when the version flips
+    pub fn sync_links(&mut self, from: usize) {
+        for i in 0..self.entities.len() {
+            let partner = match self.entities[i].link {
+                Some(partner) => partner,
+                None => continue,
+            };
+            if !self.entities[i].versions[from] || self.entities[partner].versions[from] {
+                continue;
+            }
+            let (x, y, x_speed, y_speed, facing, dead) = {
+                let entity = &self.entities[i];
+                (entity.x, entity.y, entity.x_speed, entity.y_speed, entity.facing, entity.dead)
+            };
+            let direction = match self.entities[i].entity_type {
+                EntityType::Enemy(ref enemy) => {
+                    let AI::Pace(direction) = enemy.ai;
+                    Some(direction)
+                }
+                _ => None,
+            };
+            let other = &mut self.entities[partner];
+            other.x = x;
+            other.y = y;
+            other.x_speed = x_speed;
+            other.y_speed = y_speed;
+            other.facing = facing;
+            other.dead = dead;
+            if let EntityType::Enemy(ref mut enemy) = other.entity_type {
+                if let Some(direction) = direction {
+                    enemy.ai = AI::Pace(direction);
+                }
+            }
+        }
+    }
+
+    /// Moves a push block one tile over if there is empty space behind it, then lets it fall
+    pub fn push_tile(tile_map: &mut TileMap, x: i32, mut y: i32, direction: Direction, wraparound: bool) -> bool {
+        let height = tile_map.len() as i32;
+        let width = tile_map[0].len() as i32;
+        if wraparound {
+            y = (y + height * 16) % (height * 16);
+        }
+        if x < 0 || x >= width * 16 || y < 0 || y >= height * 16 {
+            return false;
+        }
+        let (tile_x, tile_y) = ((x / 16) as usize, (y / 16) as usize);
+        match tile_map[tile_y][tile_x].tile_type {
+            TileType::PushBlock => (),
+            _ => return false,
+        }
+        let target_x = match direction {
+            Direction::Left if tile_x > 0 => tile_x - 1,
+            Direction::Right if (tile_x as i32) < width - 1 => tile_x + 1,
+            _ => return false,
+        };
+        if !Level::is_empty(&tile_map[tile_y][target_x]) {
+            return false;
+        }
+        let block = tile_map[tile_y][tile_x].clone();
+        tile_map[tile_y][tile_x] = tile_map[tile_y][target_x].clone();
+        tile_map[tile_y][target_x] = block;
+        let mut target_y = tile_y;
+        while target_y > 0 && Level::is_empty(&tile_map[target_y - 1][target_x]) {
+            let block = tile_map[target_y][target_x].clone();
+            tile_map[target_y][target_x] = tile_map[target_y - 1][target_x].clone();
+            tile_map[target_y - 1][target_x] = block;
+            target_y -= 1;
+        }
+        true
+    }
+
+    /// Tests a single pixel against the collision shape of the tile it lands in
+    pub fn is_solid_at(tile_map: &TileMap, x: i32, y: i32, wraparound: bool, phasing: bool) -> bool {
+        let tile = Level::get_tile(tile_map, x, y, wraparound);
+        tile.is_solid_for(phasing) && (y % 16 + 16) % 16 < tile.height((x % 16 + 16) % 16)
+    }
+
+    /// Platforms and the tops of ladders only catch something whose feet were above them
+    pub fn is_platform(tile_map: &TileMap, x: i32, y: i32, feet: i32, wraparound: bool) -> bool {
+        let row = if y < 0 { (y - 15) / 16 } else { y / 16 };
+        if feet < row * 16 + 16 {
+            return false;
+        }
+        match Level::get_tile(tile_map, x, y, wraparound).tile_type {
+            TileType::Platform => true,
+            TileType::Ladder => !Level::get_tile(tile_map, x, y + 16, wraparound).is_ladder(),
+            _ => false,
+        }
+    }
+
+    /// Checks just inside the corners of a 16x16 entity for spikes
+    pub fn is_hazard(tile_map: &TileMap, x: i32, y: i32, wraparound: bool) -> bool {
+        for &(offset_x, offset_y) in &[(3, 1), (12, 1), (3, 12), (12, 12)] {
+            if let TileType::Spikes = Level::get_tile(tile_map, x + offset_x, y + offset_y, wraparound).tile_type {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn is_empty(tile: &Tile) -> bool {
+        tile.sprite_id == 0 && match tile.tile_type {
+            TileType::Background => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -1181,73 +2539,222 @@ use glium::texture::Texture2d;
 
 use std::time::Instant;
 use std::rc::Rc;
-type Texture = Rc<Vec<(Rc<Texture2d>, Duration)>>;
+type Texture = Rc<SpriteSheet>;
 
 struct Animator {
+    /// Index into the sheet's animations
+    animation: usize,
+    /// Frame in the sheet
     index: usize,
+    /// Which way a ping-pong animation is going
+    forwards: bool,
+    /// A play-once animation reached its last frame and is holding it
+    finished: bool,
+    /// Markers on the first frame haven't been fired yet
+    started: bool,
     instant: Instant,
 }
+
 struct Sprite {
     texture: Texture,
-    animator: Option<Animator>,
+    animator: Animator,
 }
 
 impl Sprite {
-    pub fn load<F>(facade: &F, file: &[u8]) -> Texture
-        where F: glium::backend::Facade
-    {
+    /// `sidecar` is the GIF's metadata file, if it has one
+    pub fn load(atlas: &mut AtlasBuilder, name: &str, file: &[u8], sidecar: Option<&str>) -> Texture {
         use gif::SetParameter;
         let mut decoder = gif::Decoder::new(file);
         decoder.set(gif::ColorOutput::Indexed);
         let mut decoder = decoder.read_info().unwrap();
 
-        let mut texture: Vec<(Rc<Texture2d>, Duration)> = Vec::new();
+        let mut texture: Vec<(Image, Duration)> = Vec::new();
 
         while let Some(frame) = decoder.read_next_frame().unwrap() {
-            texture.push((Rc::new(Texture2d::new(facade,
-                                                 glium::texture::RawImage2d {
-                                                     data: frame.buffer.clone(),
-                                                     width: frame.width as u32,
-                                                     height: frame.height as u32,
-                                                     format: glium::texture::ClientFormat::U8,
-                                                 })
-                .unwrap()),
+            texture.push((atlas.add(frame.width as u32, frame.height as u32, frame.buffer.to_vec()),
                           Duration::from_millis(10 * frame.delay as u64)));
         }
-        Rc::new(texture)
+        Rc::new(SpriteSheet::new(name, texture, sidecar))
     }
 
     pub fn new(texture: &Texture) -> Sprite {
         Sprite {
             texture: texture.clone(),
-            animator: if texture.len() == 1 {
-                None
-            } else {
-                Some(Animator {
-                    index: 0,
-                    instant: Instant::now(),
-                })
+            animator: Animator {
+                animation: 0,
+                index: texture.animations[0].first,
+                forwards: true,
+                finished: false,
+                started: true,
+                instant: Instant::now(),
             },
         }
     }
 
+    /// Switches to the named animation from its start, if the sheet has one by that name
+    pub fn play(&mut self, name: &str) {
+        if let Some(animation) = self.texture.animation(name) {
+            self.animator.animation = animation;
+            self.reset();
+        }
+    }
+
     pub fn reset(&mut self) {
-        if let Some(ref mut animator) = self.animator {
-            animator.index = 0;
+        self.animator.index = self.texture.animations[self.animator.animation].first;
+        self.animator.forwards = true;
+        self.animator.finished = false;
+        self.animator.started = true;
+        self.animator.instant = Instant::now();
+    }
+
+    /// Moves on a frame once the current one has been shown long enough
+    pub fn update(&mut self) -> Vec<SpriteEvent> {
+        let mut events = Vec::new();
+        if self.animator.started {
+            self.animator.started = false;
+            self.markers(&mut events);
+        }
+        {
+            let animator = &mut self.animator;
+            let animation = &self.texture.animations[animator.animation];
+            if animator.finished || animator.instant.elapsed() <= self.texture.frames[animator.index].1 {
+                return events;
+            }
             animator.instant = Instant::now();
+            match animation.mode {
+                Mode::Loop => {
+                    if animator.index >= animation.last {
+                        animator.index = animation.first;
+                        events.push(SpriteEvent::Finished);
+                    } else {
+                        animator.index += 1;
+                    }
+                }
+                Mode::Once => {
+                    if animator.index >= animation.last {
+                        animator.finished = true;
+                        events.push(SpriteEvent::Finished);
+                        return events;
+                    }
+                    animator.index += 1;
+                }
+                Mode::PingPong => {
+                    if animator.forwards && animator.index >= animation.last {
+                        animator.forwards = false;
+                    } else if !animator.forwards && animator.index <= animation.first {
+                        animator.forwards = true;
+                        events.push(SpriteEvent::Finished);
+                    }
+                    if animator.forwards && animator.index < animation.last {
+                        animator.index += 1;
+                    } else if !animator.forwards && animator.index > animation.first {
+                        animator.index -= 1;
+                    }
+                }
+            }
         }
+        self.markers(&mut events);
+        events
     }
 
-    pub fn texture(&mut self) -> Rc<Texture2d> {
-        match self.animator {
-            Some(ref mut animator) => {
-                if animator.instant.elapsed() > self.texture[animator.index].1 {
-                    animator.instant = Instant::now();
-                    animator.index = (animator.index + 1) % self.texture.len();
-                }
-                self.texture[animator.index].0.clone()
+    /// Adds the markers on the frame being shown
+    fn markers(&self, events: &mut Vec<SpriteEvent>) {
+        for &(frame, ref name) in &self.texture.markers {
+            if frame == self.animator.index {
+                events.push(SpriteEvent::Marker(name.clone()));
             }
-            None => self.texture[0].0.clone(),
         }
     }
+
+    /// A play-once animation is holding its last frame
+    pub fn finished(&self) -> bool {
+        self.animator.finished
+    }
+
+    /// The frame being shown, without moving the animation on
+    pub fn frame(&self) -> Image {
+        self.texture.frames[self.animator.index].0.clone()
+    }
+
+    /// Moves the animation on and returns the frame to draw, for sprites nothing reacts to
+    pub fn texture(&mut self) -> Image {
+        self.update();
+        self.frame()
+    }
+
+    /// How far left and down of the entity the frame is drawn, `flip` mirrors it like the sprite
+    pub fn origin(&self, flip: bool) -> [i32; 2] {
+        let width = self.texture.frames[self.animator.index].0.width as i32;
+        let origin = self.texture.origin;
+        [if flip { width - 16 - origin[0] } else { origin[0] }, origin[1]]
+    }
+
+    /// The current frame's hitbox in level pixels for an entity at `position`
+    pub fn hitbox(&self, position: [i32; 2], flip: bool) -> [i32; 4] {
+        let width = self.texture.frames[self.animator.index].0.width as i32;
+        let hitbox = self.texture.hitboxes[self.animator.index];
+        let origin = self.origin(flip);
+        let x = if flip { width - hitbox[0] - hitbox[2] } else { hitbox[0] };
+        [position[0] - origin[0] + x, position[1] - origin[1] + hitbox[1], hitbox[2], hitbox[3]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use layers::Layer;
+    use super::{Level, Tile, TileType};
+
+    fn tile(shape: &str) -> Tile {
+        Tile {
+            sprite_id: 0,
+            tile_type: TileType::Solid,
+            shape: Tile::parse_shape(shape).unwrap(),
+            layer: Layer::BackgroundTiles,
+        }
+    }
+
+    fn heights(shape: &str) -> Vec<i32> {
+        let tile = tile(shape);
+        (0..16).map(|x| tile.height(x)).collect()
+    }
+
+    #[test]
+    fn slope_heights() {
+        assert_eq!(heights("slope45_up"), (1..17).collect::<Vec<i32>>());
+        assert_eq!(heights("slope45_down"), (1..17).rev().collect::<Vec<i32>>());
+        assert_eq!(heights("slope22_up_low"), vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8]);
+        assert_eq!(heights("slope22_up_high"), vec![8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 14, 14, 15, 15, 16]);
+        assert_eq!(heights("half"), vec![8; 16]);
+        assert_eq!(heights("full"), vec![16; 16]);
+        // Slopes going down are the ones going up mirrored
+        for &(up, down) in &[("slope45_up", "slope45_down"), ("slope22_up_low", "slope22_down_low"), ("slope22_up_high", "slope22_down_high")] {
+            let mut mirrored = heights(up);
+            mirrored.reverse();
+            assert_eq!(heights(down), mirrored);
+        }
+    }
+
+    #[test]
+    fn shape_names() {
+        assert_eq!(Tile::parse_shape("4:12"), Some([4, 12]));
+        assert_eq!(Tile::parse_shape(" Slope45_Up "), Some([0, 16]));
+        assert_eq!(Tile::parse_shape("4:17"), None);
+        assert_eq!(Tile::parse_shape("4:"), None);
+        assert_eq!(Tile::parse_shape("foregorund"), None);
+    }
+
+    #[test]
+    fn solid_inside_slope() {
+        // One row of a 45 degree slope next to a half tile
+        let tile_map = vec![vec![Rc::new(tile("slope45_up")), Rc::new(tile("half"))]];
+        assert!(Level::is_solid_at(&tile_map, 0, 0, false, false));
+        assert!(!Level::is_solid_at(&tile_map, 0, 1, false, false));
+        assert!(Level::is_solid_at(&tile_map, 10, 10, false, false));
+        assert!(!Level::is_solid_at(&tile_map, 10, 11, false, false));
+        assert!(Level::is_solid_at(&tile_map, 15, 15, false, false));
+        assert!(Level::is_solid_at(&tile_map, 20, 7, false, false));
+        assert!(!Level::is_solid_at(&tile_map, 20, 8, false, false));
+        assert!(!Level::is_solid_at(&tile_map, 40, 0, false, false));
+    }
 }
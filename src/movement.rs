@@ -0,0 +1,106 @@
+use data;
+
+/// How the player moves and what it can do, loaded from `player/movement` so each form can be tuned without a rebuild
+#[derive(Clone, Copy, Debug)]
+pub struct MovementProfile {
+    pub walk_speed: f32,
+    pub acceleration: f32,
+    pub friction: f32,
+    pub gravity: f32,
+    pub max_fall: f32,
+    pub jump_speed: f32,
+    /// Upwards speed is multiplied by this when the jump button is let go early
+    pub jump_release: f32,
+    /// Frames after walking off a ledge where a jump is still allowed
+    pub coyote_frames: u8,
+    /// Frames a jump press is remembered before landing
+    pub jump_buffer: u8,
+    pub climb_speed: f32,
+    pub abilities: Abilities,
+}
+
+/// What a form can do beyond walking and jumping, so puzzles can be built around swapping forms
+#[derive(Clone, Copy, Debug)]
+pub struct Abilities {
+    /// Gravity and fall speed multiplier while jump is held on the way down
+    pub float: f32,
+    /// Extra jumps allowed before landing again
+    pub air_jumps: u8,
+    /// Walks through phase blocks
+    pub phase: bool,
+    /// Shoves push blocks when walking into them
+    pub push: bool,
+}
+
+impl Default for Abilities {
+    fn default() -> Self {
+        Abilities {
+            float: 1.0,
+            air_jumps: 0,
+            phase: false,
+            push: false,
+        }
+    }
+}
+
+impl Default for MovementProfile {
+    fn default() -> Self {
+        MovementProfile {
+            walk_speed: 2.0,
+            acceleration: 2.0,
+            friction: 0.75,
+            gravity: 0.18,
+            max_fall: 2.0,
+            jump_speed: 4.0,
+            jump_release: 1.0,
+            coyote_frames: 0,
+            jump_buffer: 0,
+            climb_speed: 1.0,
+            abilities: Abilities::default(),
+        }
+    }
+}
+
+impl MovementProfile {
+    /// Parses `name,value` lines, anything after a LIFE or DEATH line only applies to that version
+    pub fn parse(string: &str) -> [MovementProfile; 2] {
+        let mut profiles = [MovementProfile::default(), MovementProfile::default()];
+        let mut versions = [true, true];
+        data::key_values("player/movement", string, |name, value| {
+            match name {
+                "life" => versions = [true, false],
+                "death" => versions = [false, true],
+                _ => {
+                    for i in 0..2 {
+                        if versions[i] {
+                            try!(profiles[i].set(name, value));
+                        }
+                    }
+                }
+            }
+            Ok(())
+        });
+        profiles
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "walk_speed" => self.walk_speed = try!(data::parse(value)),
+            "acceleration" => self.acceleration = try!(data::parse(value)),
+            "friction" => self.friction = try!(data::parse(value)),
+            "gravity" => self.gravity = try!(data::parse(value)),
+            "max_fall" => self.max_fall = try!(data::parse(value)),
+            "jump_speed" => self.jump_speed = try!(data::parse(value)),
+            "jump_release" => self.jump_release = try!(data::parse(value)),
+            "coyote_frames" => self.coyote_frames = try!(data::parse(value)),
+            "jump_buffer" => self.jump_buffer = try!(data::parse(value)),
+            "climb_speed" => self.climb_speed = try!(data::parse(value)),
+            "float" => self.abilities.float = try!(data::parse(value)),
+            "air_jumps" => self.abilities.air_jumps = try!(data::parse(value)),
+            "phase" => self.abilities.phase = try!(data::parse(value)),
+            "push" => self.abilities.push = try!(data::parse(value)),
+            _ => return Err(format!("unknown movement value {}", name)),
+        }
+        Ok(())
+    }
+}
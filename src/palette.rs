@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use gif;
+use data;
+
+/// A life and a death palette that can be picked together from the options, darkest colour first
+pub struct PalettePair {
+    /// File name under `palettes/`, which is what settings remember
+    pub id: String,
+    /// Shown in the menu, may be an `@key` into the string table
+    pub name: String,
+    pub colours: [[[u8; 3]; 4]; 2],
+}
+
+/// Reads every `palettes/<id>` text file, `images` holds the palette GIFs they can take colours from
+pub fn load(data: &HashMap<String, String>, images: &HashMap<String, Vec<u8>>) -> Vec<PalettePair> {
+    let mut pairs = Vec::new();
+    for (name, content) in data {
+        if !name.starts_with("palettes/") {
+            continue;
+        }
+        let mut pair = PalettePair {
+            id: name[9..].to_string(),
+            name: name[9..].to_string(),
+            // Kept for anything the file leaves out or gets wrong
+            colours: [::LIFE_PALETTE, ::DEATH_PALETTE],
+        };
+        for line in content.lines() {
+            let values = data::values(line);
+            let version = match values[0] {
+                "" => continue,
+                "name" => {
+                    match values.get(1) {
+                        Some(value) if !value.is_empty() => pair.name = value.to_string(),
+                        _ => data::report(name, line, "missing name"),
+                    }
+                    continue;
+                }
+                "life" => 0,
+                "death" => 1,
+                other => {
+                    data::report(name, line, &format!("unknown palette value {}", other));
+                    continue;
+                }
+            };
+            // Either four hex colours or the name of a GIF whose first four colours are used
+            let result = if values.len() >= 5 {
+                values[1..5].iter().map(|value| hex(value)).collect::<Result<Vec<[u8; 3]>, String>>().map(|hexes| {
+                    for i in 0..4 {
+                        pair.colours[version][i] = hexes[i];
+                    }
+                })
+            } else {
+                match images.get(&format!("palettes/{}", data::get(&values, 1))) {
+                    Some(image) => from_image(image).map(|colours| pair.colours[version] = colours),
+                    None => Err(format!("no palette image {}", data::get(&values, 1))),
+                }
+            };
+            if let Err(problem) = result {
+                data::report(name, line, &problem);
+            }
+        }
+        pairs.push(pair);
+    }
+    pairs.sort_by(|a, b| (a.id != "classic", &a.id).cmp(&(b.id != "classic", &b.id)));
+    pairs
+}
+
+fn hex(value: &str) -> Result<[u8; 3], String> {
+    match u32::from_str_radix(value.trim_left_matches('#'), 16) {
+        Ok(number) if number <= 0xffffff => Ok([(number >> 16) as u8, (number >> 8) as u8, number as u8]),
+        _ => Err(format!("bad colour {}", value)),
+    }
+}
+
+fn from_image(file: &[u8]) -> Result<[[u8; 3]; 4], String> {
+    let decoder = try!(gif::Decoder::new(file).read_info().map_err(|error| error.to_string()));
+    let palette = match decoder.global_palette() {
+        Some(palette) if palette.len() >= 12 => palette,
+        _ => return Err("palette image needs four colours".to_string()),
+    };
+    let mut colours = [[0; 3]; 4];
+    for i in 0..4 {
+        colours[i] = [palette[i * 3], palette[i * 3 + 1], palette[i * 3 + 2]];
+    }
+    Ok(colours)
+}
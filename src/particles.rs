@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use animation::SpriteSheet;
+use atlas::Image;
+use data;
+
+/// Gameplay moments that effects hang off, the same ones that have sounds
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GameEvent {
+    Jump,
+    Land,
+    KeyGet,
+    Hurt,
+    /// The start of a life/death transition
+    Switch,
+    EnemyDeath,
+    /// Fired from a sprite's frame markers
+    Footstep,
+}
+
+impl GameEvent {
+    pub fn parse(name: &str) -> Option<GameEvent> {
+        match name {
+            "jump" => Some(GameEvent::Jump),
+            "land" => Some(GameEvent::Land),
+            "key" => Some(GameEvent::KeyGet),
+            "hurt" => Some(GameEvent::Hurt),
+            "switch" => Some(GameEvent::Switch),
+            "enemy_death" => Some(GameEvent::EnemyDeath),
+            "footstep" => Some(GameEvent::Footstep),
+            _ => None,
+        }
+    }
+}
+
+/// A burst of particles fired by an event
+pub struct Emitter {
+    /// Frames are spread evenly over each particle's life
+    sprite: Rc<SpriteSheet>,
+    count: u32,
+    lifetime: u32,
+    /// Lowest and highest starting speed on each axis, up is positive
+    x_speed: [f32; 2],
+    y_speed: [f32; 2],
+    gravity: f32,
+    versions: [bool; 2],
+}
+
+/// Parses `event,sprite,count,lifetime,min_x_speed,max_x_speed,min_y_speed,max_y_speed,gravity,versions` lines, where
+/// versions is life, death or both
+pub fn load(string: &str, textures: &HashMap<String, Rc<SpriteSheet>>) -> HashMap<GameEvent, Vec<Emitter>> {
+    let mut emitters = HashMap::new();
+    for line in string.lines() {
+        let values = data::values(line);
+        if values[0].is_empty() {
+            continue;
+        }
+        match emitter(&values, textures) {
+            Ok((event, emitter)) => emitters.entry(event).or_insert_with(Vec::new).push(emitter),
+            Err(problem) => data::report("particles", line, &problem),
+        }
+    }
+    emitters
+}
+
+fn emitter(values: &[&str], textures: &HashMap<String, Rc<SpriteSheet>>) -> Result<(GameEvent, Emitter), String> {
+    let event = try!(GameEvent::parse(values[0]).ok_or(format!("unknown particle event {}", values[0])));
+    let sprite = try!(textures.get(data::get(values, 1)).ok_or(format!("no sprite {}", data::get(values, 1))));
+    let number = |i: usize| -> Result<f32, String> { data::parse(data::get(values, i)) };
+    let emitter = Emitter {
+        sprite: sprite.clone(),
+        count: try!(data::parse(data::get(values, 2))),
+        lifetime: try!(data::parse::<u32>(data::get(values, 3))).max(1),
+        x_speed: [try!(number(4)), try!(number(5))],
+        y_speed: [try!(number(6)), try!(number(7))],
+        gravity: try!(number(8)),
+        versions: match data::get(values, 9) {
+            "life" => [true, false],
+            "death" => [false, true],
+            _ => [true, true],
+        },
+    };
+    Ok((event, emitter))
+}
+
+struct Particle {
+    sprite: Rc<SpriteSheet>,
+    x: f32,
+    y: f32,
+    x_speed: f32,
+    y_speed: f32,
+    gravity: f32,
+    age: u32,
+    lifetime: u32,
+    versions: [bool; 2],
+}
+
+/// Particles alive in a level, in level pixels
+pub struct Particles {
+    particles: Vec<Particle>,
+    seed: u32,
+}
+
+impl Particles {
+    pub fn new() -> Particles {
+        Particles {
+            particles: Vec::new(),
+            seed: 0x6c07_8965,
+        }
+    }
+
+    pub fn spawn(&mut self, emitters: &HashMap<GameEvent, Vec<Emitter>>, event: GameEvent, position: [i32; 2], version: usize) {
+        for emitter in emitters.get(&event).map_or(&[][..], |emitters| &emitters[..]) {
+            if !emitter.versions[version] {
+                continue;
+            }
+            for _ in 0..emitter.count {
+                let x_speed = self.between(emitter.x_speed);
+                let y_speed = self.between(emitter.y_speed);
+                self.particles.push(Particle {
+                    sprite: emitter.sprite.clone(),
+                    x: position[0] as f32,
+                    y: position[1] as f32,
+                    x_speed: x_speed,
+                    y_speed: y_speed,
+                    gravity: emitter.gravity,
+                    age: 0,
+                    lifetime: emitter.lifetime,
+                    versions: emitter.versions,
+                });
+            }
+        }
+    }
+
+    pub fn step(&mut self) {
+        for particle in &mut self.particles {
+            particle.x += particle.x_speed;
+            particle.y += particle.y_speed;
+            particle.y_speed -= particle.gravity;
+            particle.age += 1;
+        }
+        self.particles.retain(|particle| particle.age < particle.lifetime);
+    }
+
+    /// Sprites centred on each particle, relative to `camera`
+    pub fn sprites(&self, camera: [i32; 2], version: usize) -> Vec<(Image, [i32; 2], bool)> {
+        let mut sprites = Vec::new();
+        for particle in &self.particles {
+            if !particle.versions[version] {
+                continue;
+            }
+            let frame = &particle.sprite.frames[particle.age as usize * particle.sprite.frames.len() / particle.lifetime as usize].0;
+            sprites.push((frame.clone(),
+                          [particle.x as i32 - frame.width as i32 / 2 - camera[0], particle.y as i32 - frame.height as i32 / 2 - camera[1]],
+                          false));
+        }
+        sprites
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    /// A random number from `range[0]` to `range[1]`
+    fn between(&mut self, range: [f32; 2]) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        range[0] + (range[1] - range[0]) * (self.seed % 1000) as f32 / 999.0
+    }
+}
@@ -0,0 +1,22 @@
+#version 140
+
+in vec2 screen_coord;
+out vec4 color;
+
+uniform sampler2D lcd;
+// Window pixels per screen pixel
+uniform float factor;
+uniform float grid;
+uniform float scanlines;
+
+void main() {
+	color = texelFetch(lcd, ivec2(screen_coord), 0);
+	// Darken the last window pixel of each screen pixel, once there's room for a gap
+	vec2 inside = fract(screen_coord) * factor;
+	if (factor >= 3.0 && (inside.x >= factor - 1.0 || inside.y < 1.0)) {
+		color.rgb *= 1.0 - grid;
+	}
+	if (mod(gl_FragCoord.y, 2.0) < 1.0) {
+		color.rgb *= 1.0 - scanlines;
+	}
+}
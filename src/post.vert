@@ -0,0 +1,10 @@
+#version 140
+
+in vec2 position;
+
+out vec2 screen_coord;
+
+void main() {
+    screen_coord = position * vec2(160, 144);
+	gl_Position = vec4(position * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use data;
+
+/// Where settings are kept, next to wherever the game is run from
+const PATH: &'static str = "settings.txt";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Start,
+}
+
+pub const ACTIONS: [Action; 7] = [Action::Up, Action::Down, Action::Left, Action::Right, Action::A, Action::B, Action::Start];
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::A => "a",
+            Action::B => "b",
+            Action::Start => "start",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Action> {
+        ACTIONS.iter().find(|action| action.name() == name).cloned()
+    }
+}
+
+/// Everything the player can change from the options, saved whenever it changes
+pub struct Settings {
+    /// Window size as a multiple of 160x144 when not fullscreen
+    pub scale: u32,
+    pub fullscreen: bool,
+    /// From 0 to 10
+    pub volume: u8,
+    pub palette: String,
+    pub language: String,
+    pub show_hud: bool,
+    /// Post-processing, see `effects`
+    pub lcd_grid: bool,
+    pub ghosting: bool,
+    pub scanlines: bool,
+    /// Dithers between palettes during transitions instead of stepping
+    pub dither: bool,
+    /// Name of the gamepad to listen to, "any" or "off"
+    pub gamepad: String,
+    /// How far the stick has to move before it counts as a direction
+    pub deadzone: f32,
+    /// Keys by their glutin `VirtualKeyCode` name and gamepad buttons as `Pad` and their name, an action can have several
+    pub bindings: Vec<(String, Action)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            scale: 3,
+            fullscreen: false,
+            volume: 10,
+            palette: "classic".to_string(),
+            language: "en".to_string(),
+            show_hud: true,
+            lcd_grid: false,
+            ghosting: false,
+            scanlines: false,
+            dither: true,
+            gamepad: "any".to_string(),
+            deadzone: 0.4,
+            bindings: default_bindings(),
+        }
+    }
+}
+
+fn default_bindings() -> Vec<(String, Action)> {
+    vec![("Up".to_string(), Action::Up),
+         ("Down".to_string(), Action::Down),
+         ("Left".to_string(), Action::Left),
+         ("Right".to_string(), Action::Right),
+         ("X".to_string(), Action::A),
+         ("Z".to_string(), Action::B),
+         ("Return".to_string(), Action::Start),
+         ("PadDPadUp".to_string(), Action::Up),
+         ("PadDPadDown".to_string(), Action::Down),
+         ("PadDPadLeft".to_string(), Action::Left),
+         ("PadDPadRight".to_string(), Action::Right),
+         ("PadStickUp".to_string(), Action::Up),
+         ("PadStickDown".to_string(), Action::Down),
+         ("PadStickLeft".to_string(), Action::Left),
+         ("PadStickRight".to_string(), Action::Right),
+         ("PadSouth".to_string(), Action::A),
+         ("PadEast".to_string(), Action::B),
+         ("PadStart".to_string(), Action::Start)]
+}
+
+impl Settings {
+    /// Reads the settings file, anything missing from it keeps its default
+    pub fn load() -> Settings {
+        let mut settings = Settings::default();
+        let mut string = String::new();
+        if let Ok(mut file) = File::open(PATH) {
+            if file.read_to_string(&mut string).is_err() {
+                string.clear();
+            }
+        }
+        let mut rebound = false;
+        data::key_values(PATH, &string, |name, value| {
+            match name {
+                "scale" => settings.scale = try!(data::parse::<u32>(value)).max(1),
+                "fullscreen" => settings.fullscreen = try!(data::parse(value)),
+                "volume" => settings.volume = try!(data::parse::<u8>(value)).min(10),
+                "palette" => settings.palette = value.to_string(),
+                "language" => settings.language = value.to_string(),
+                "hud" => settings.show_hud = try!(data::parse(value)),
+                "lcd_grid" => settings.lcd_grid = try!(data::parse(value)),
+                "ghosting" => settings.ghosting = try!(data::parse(value)),
+                "scanlines" => settings.scanlines = try!(data::parse(value)),
+                "dither" => settings.dither = try!(data::parse(value)),
+                "gamepad" => settings.gamepad = value.to_string(),
+                "deadzone" => settings.deadzone = try!(data::parse(value)),
+                // Bindings are read afterwards
+                "bind" => (),
+                _ => return Err(format!("unknown setting {}", name)),
+            }
+            Ok(())
+        });
+        // bind,action,key lines replace the default bindings once any are given
+        for line in string.lines() {
+            let values = data::values(line);
+            if values[0] != "bind" {
+                continue;
+            }
+            match (Action::parse(data::get(&values, 1)), data::get(&values, 2)) {
+                (Some(action), key) if !key.is_empty() => {
+                    if !rebound {
+                        settings.bindings.clear();
+                        rebound = true;
+                    }
+                    settings.bindings.push((key.to_string(), action));
+                }
+                _ => data::report(PATH, line, "bad binding"),
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        let mut string = format!("scale,{}\nfullscreen,{}\nvolume,{}\npalette,{}\nlanguage,{}\nhud,{}\nlcd_grid,{}\nghosting,{}\nscanlines,{}\ndither,{}\n\
+                                  gamepad,{}\ndeadzone,{}\n",
+                                 self.scale,
+                                 self.fullscreen,
+                                 self.volume,
+                                 self.palette,
+                                 self.language,
+                                 self.show_hud,
+                                 self.lcd_grid,
+                                 self.ghosting,
+                                 self.scanlines,
+                                 self.dither,
+                                 self.gamepad,
+                                 self.deadzone);
+        for &(ref key, action) in &self.bindings {
+            string.push_str(&format!("bind,{},{}\n", action.name(), key));
+        }
+        match File::create(PATH) {
+            Ok(mut file) => file.write_all(string.as_bytes()).unwrap(),
+            Err(error) => println!("Couldn't save settings: {}", error),
+        }
+    }
+
+    pub fn actions(&self, key: &str) -> Vec<Action> {
+        self.bindings.iter().filter(|binding| binding.0 == key).map(|binding| binding.1).collect()
+    }
+
+    pub fn keys(&self, action: Action) -> Vec<&str> {
+        self.bindings.iter().filter(|binding| binding.1 == action).map(|binding| binding.0.as_ref()).collect()
+    }
+
+    /// Adds `key` to `action`, taking it away from any other action and keeping at most two keys and two pad buttons
+    /// per action
+    pub fn bind(&mut self, action: Action, key: &str) {
+        self.bindings.retain(|binding| binding.0 != key);
+        let pad = key.starts_with("Pad");
+        let same_kind = |binding: &(String, Action)| binding.1 == action && binding.0.starts_with("Pad") == pad;
+        if self.bindings.iter().filter(|binding| same_kind(binding)).count() >= 2 {
+            let oldest = self.bindings.iter().position(|binding| same_kind(binding)).unwrap();
+            self.bindings.remove(oldest);
+        }
+        self.bindings.push((key.to_string(), action));
+    }
+
+    pub fn reset_bindings(&mut self) {
+        self.bindings = default_bindings();
+    }
+}
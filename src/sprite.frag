@@ -1,11 +1,11 @@
 #version 140
 
-in vec2 tex_coord;
+in vec2 atlas_coord;
 out vec4 color;
 
-uniform sampler2D tex;
+uniform sampler2D atlas;
 uniform sampler1D palette;
 
 void main() {
-	color = texelFetch(palette, int(texelFetch(tex, ivec2(tex_coord.x, tex_coord.y), 0).x * 256.0), 0);
+	color = texelFetch(palette, int(texelFetch(atlas, ivec2(atlas_coord), 0).x * 256.0), 0);
 }
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use data;
+
+/// How many palettes `expand_palettes` makes
+const PALETTES: usize = 7;
+
+/// Something a transition makes happen in the level on the first frame of a keyframe
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransitionEvent {
+    /// Brings the player back in the other version, at the checkpoint when returning to life
+    Respawn,
+}
+
+/// What starts a transition, each one has its own sequence
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Trigger {
+    Dying,
+    Reviving,
+}
+
+#[derive(Clone, Debug)]
+pub struct Keyframe {
+    /// How many frames it's shown for
+    pub frames: u32,
+    /// Index into the palettes made by `expand_palettes`
+    pub palette: usize,
+    /// Frame of `Fade` drawn over the screen
+    pub fade: Option<usize>,
+    pub event: Option<TransitionEvent>,
+    /// Dithers into the next keyframe's palette over its frames instead of switching in one step
+    pub dither: bool,
+}
+
+/// A timeline of keyframes played in order while the level is paused
+#[derive(Clone, Debug)]
+pub struct Transition {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Transition {
+    /// Parses `frames,palette,fade,event,dither` lines, where everything after the palette can be left empty
+    pub fn parse(file: &str, string: &str) -> Transition {
+        let mut keyframes = Vec::new();
+        for line in string.lines() {
+            let values = data::values(line);
+            if values[0].is_empty() {
+                continue;
+            }
+            match Transition::keyframe(&values) {
+                Ok(keyframe) => keyframes.push(keyframe),
+                Err(problem) => data::report(file, line, &problem),
+            }
+        }
+        Transition { keyframes: keyframes }
+    }
+
+    fn keyframe(values: &[&str]) -> Result<Keyframe, String> {
+        let palette = try!(data::parse(data::get(values, 1)));
+        if palette >= PALETTES {
+            return Err(format!("no palette {}", palette));
+        }
+        Ok(Keyframe {
+            frames: try!(data::parse(values[0])),
+            palette: palette,
+            fade: data::get(values, 2).parse().ok(),
+            event: match data::get(values, 3) {
+                "" => None,
+                "respawn" => Some(TransitionEvent::Respawn),
+                other => return Err(format!("unknown transition event {}", other)),
+            },
+            dither: data::get(values, 4) == "dither",
+        })
+    }
+
+    /// The keyframe shown on `frame` and whether that's its first frame, or `None` once it's over
+    pub fn at(&self, frame: u32) -> Option<(&Keyframe, bool)> {
+        let mut start = 0;
+        for keyframe in &self.keyframes {
+            if frame < start + keyframe.frames {
+                return Some((keyframe, frame == start));
+            }
+            start += keyframe.frames;
+        }
+        None
+    }
+
+    /// The palette being dithered into on `frame` and how far along the fade is
+    pub fn fade_to(&self, frame: u32) -> Option<(usize, f32)> {
+        let mut start = 0;
+        for (i, keyframe) in self.keyframes.iter().enumerate() {
+            if frame < start + keyframe.frames {
+                return match self.keyframes.get(i + 1) {
+                    Some(next) if keyframe.dither => Some((next.palette, (frame - start) as f32 / keyframe.frames as f32)),
+                    _ => None,
+                };
+            }
+            start += keyframe.frames;
+        }
+        None
+    }
+}
+
+/// The sequences for every trigger, loaded from `transitions/<trigger>`
+#[derive(Clone, Debug)]
+pub struct Transitions {
+    pub dying: Transition,
+    pub reviving: Transition,
+}
+
+impl Transitions {
+    pub fn load(data: &HashMap<String, String>) -> Transitions {
+        Transitions {
+            dying: Transition::parse("transitions/dying", data.get("transitions/dying").unwrap()),
+            reviving: Transition::parse("transitions/reviving", data.get("transitions/reviving").unwrap()),
+        }
+    }
+
+    pub fn get(&self, trigger: Trigger) -> &Transition {
+        match trigger {
+            Trigger::Dying => &self.dying,
+            Trigger::Reviving => &self.reviving,
+        }
+    }
+}